@@ -0,0 +1,6 @@
+-- Databases created by hand before migrations existed already have this table.
+CREATE TABLE IF NOT EXISTS tasks (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    priority INTEGER
+);
@@ -0,0 +1,3 @@
+ALTER TABLE tasks
+    ADD COLUMN completed BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN completed_at TIMESTAMPTZ;
@@ -0,0 +1,6 @@
+ALTER TABLE tasks
+    ADD COLUMN start_at TIMESTAMPTZ,
+    ADD COLUMN due_at TIMESTAMPTZ,
+    ADD CONSTRAINT tasks_start_before_due CHECK (start_at <= due_at);
+
+CREATE INDEX tasks_due_at_idx ON tasks (due_at) WHERE due_at IS NOT NULL;
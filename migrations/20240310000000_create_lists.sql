@@ -0,0 +1,18 @@
+CREATE TABLE lists (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- Same syntax as the `sort` query parameter, e.g. `priority,-id`.
+    default_sort TEXT NOT NULL DEFAULT 'id',
+    is_default BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+-- Tasks created without a list go to the single default list.
+CREATE UNIQUE INDEX lists_is_default_key ON lists (is_default) WHERE is_default;
+
+INSERT INTO lists (name, is_default) VALUES ('Inbox', TRUE);
+
+ALTER TABLE tasks ADD COLUMN list_id INTEGER REFERENCES lists (id) ON DELETE CASCADE;
+UPDATE tasks SET list_id = (SELECT id FROM lists WHERE is_default);
+ALTER TABLE tasks ALTER COLUMN list_id SET NOT NULL;
+
+CREATE INDEX tasks_list_id_idx ON tasks (list_id);
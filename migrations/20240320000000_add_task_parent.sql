@@ -0,0 +1,6 @@
+-- Deleting a parent deletes its subtree; the API refuses unless asked to cascade.
+ALTER TABLE tasks
+    ADD COLUMN parent_id INTEGER REFERENCES tasks (id) ON DELETE CASCADE,
+    ADD CONSTRAINT tasks_parent_not_self CHECK (parent_id <> id);
+
+CREATE INDEX tasks_parent_id_idx ON tasks (parent_id);
@@ -0,0 +1,35 @@
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    email TEXT NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
+
+-- Only a SHA-256 hash of each session token is stored.
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+
+-- Tasks created before accounts existed have no owner and are not visible to anyone.
+ALTER TABLE tasks ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+
+CREATE INDEX tasks_owner_id_idx ON tasks (owner_id);
+
+-- Lists and tags belong to a user too, and like tasks, existing ones stay invisible. Each user
+-- gets an Inbox as their default list when they register.
+ALTER TABLE lists ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+DROP INDEX lists_is_default_key;
+CREATE UNIQUE INDEX lists_is_default_key ON lists (owner_id) WHERE is_default;
+
+ALTER TABLE tags ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+DROP INDEX tags_name_key;
+
+-- Tag names are still matched case-insensitively, now per user.
+CREATE UNIQUE INDEX tags_name_key ON tags (owner_id, lower(name));
@@ -0,0 +1,69 @@
+CREATE TABLE workspaces (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- Set on each user's personal workspace, where their tasks go unless they pick another.
+    personal_for INTEGER UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE workspace_members (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role TEXT NOT NULL
+        CONSTRAINT workspace_members_role_check CHECK (role IN ('owner', 'editor', 'viewer')),
+    PRIMARY KEY (workspace_id, user_id)
+);
+
+CREATE INDEX workspace_members_user_id_idx ON workspace_members (user_id);
+
+INSERT INTO workspaces (name, personal_for) SELECT 'Personal', id FROM users;
+INSERT INTO workspace_members (workspace_id, user_id, role)
+SELECT id, personal_for, 'owner' FROM workspaces;
+
+-- Tasks now belong to a workspace, and access follows workspace membership. Ownerless tasks
+-- stay without a workspace and remain invisible.
+ALTER TABLE tasks ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id) ON DELETE CASCADE;
+
+UPDATE tasks SET workspace_id = workspaces.id
+FROM workspaces
+WHERE workspaces.personal_for = tasks.owner_id;
+
+CREATE INDEX tasks_workspace_id_idx ON tasks (workspace_id);
+
+-- `owner_id` now only records who created the task, so a shared task outlives its creator.
+ALTER TABLE tasks
+    DROP CONSTRAINT tasks_owner_id_fkey,
+    ADD CONSTRAINT tasks_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES users (id) ON DELETE SET NULL;
+
+-- Lists and tags move from their owner to the owner's personal workspace, along with the
+-- owner's tasks.
+ALTER TABLE lists ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id) ON DELETE CASCADE;
+
+UPDATE lists SET workspace_id = workspaces.id
+FROM workspaces
+WHERE workspaces.personal_for = lists.owner_id;
+
+DROP INDEX lists_is_default_key;
+ALTER TABLE lists DROP COLUMN owner_id;
+
+-- Tasks created without a list go to their workspace's default list.
+CREATE UNIQUE INDEX lists_is_default_key ON lists (workspace_id) WHERE is_default;
+ALTER TABLE lists ADD CONSTRAINT lists_id_workspace_id_key UNIQUE (id, workspace_id);
+
+-- A task's list must be in the task's workspace, so deleting a list only ever deletes tasks of
+-- that workspace. Deferred, so that it's checked after the cascade on `list_id` has run.
+ALTER TABLE tasks
+    ADD CONSTRAINT tasks_list_id_workspace_id_fkey FOREIGN KEY (list_id, workspace_id)
+    REFERENCES lists (id, workspace_id) DEFERRABLE INITIALLY DEFERRED;
+
+ALTER TABLE tags ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id) ON DELETE CASCADE;
+
+UPDATE tags SET workspace_id = workspaces.id
+FROM workspaces
+WHERE workspaces.personal_for = tags.owner_id;
+
+DROP INDEX tags_name_key;
+ALTER TABLE tags DROP COLUMN owner_id;
+
+-- Tag names are still matched case-insensitively, now within a workspace.
+CREATE UNIQUE INDEX tags_name_key ON tags (workspace_id, lower(name));
@@ -0,0 +1,19 @@
+-- Defense in depth for workspace isolation. Each request runs in a transaction with
+-- `app.user_id` set to the caller, and tasks outside the caller's workspaces can be neither read
+-- nor written, even by a query that forgets to filter. With `app.user_id` unset, no tasks are
+-- visible. Code acting on behalf of no particular user, such as migrations and background jobs,
+-- must opt out explicitly by setting `app.bypass_rls` to `on` for its transaction.
+--
+-- Superusers and roles with BYPASSRLS are always exempt, so the server must connect as an
+-- ordinary role for the policy to take effect. FORCE makes it apply to the table owner too.
+ALTER TABLE tasks ENABLE ROW LEVEL SECURITY;
+ALTER TABLE tasks FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY tasks_workspace_members ON tasks
+    USING (
+        current_setting('app.bypass_rls', TRUE) = 'on'
+        OR workspace_id IN (
+            SELECT workspace_id FROM workspace_members
+            WHERE user_id = NULLIF(current_setting('app.user_id', TRUE), '')::INTEGER
+        )
+    );
@@ -0,0 +1,16 @@
+ALTER TABLE tasks ADD COLUMN assignee_id INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE INDEX tasks_assignee_id_idx ON tasks (assignee_id);
+
+-- One row per change of a task's assignee, including the first assignment and unassignment.
+CREATE TABLE task_assignment_events (
+    id BIGSERIAL PRIMARY KEY,
+    task_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+    -- Who made the change.
+    actor_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    previous_assignee_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    assignee_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX task_assignment_events_task_id_idx ON task_assignment_events (task_id);
@@ -0,0 +1,13 @@
+-- `body` is the Markdown the author wrote; `body_html` is its sanitized rendering.
+CREATE TABLE comments (
+    id SERIAL PRIMARY KEY,
+    task_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+    author_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    body TEXT NOT NULL,
+    body_html TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Set when the comment is edited.
+    updated_at TIMESTAMPTZ
+);
+
+CREATE INDEX comments_task_id_idx ON comments (task_id);
@@ -0,0 +1,33 @@
+-- The file itself lives in the configured storage backend under `storage_key`.
+CREATE TABLE attachments (
+    id SERIAL PRIMARY KEY,
+    task_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+    uploader_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    file_name TEXT NOT NULL,
+    -- Sniffed from the file's contents rather than taken from the client.
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    storage_key TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX attachments_task_id_idx ON attachments (task_id);
+
+-- Attachments also disappear when their task, list or workspace is deleted, so files are queued
+-- for removal from storage by a trigger rather than by each handler that can delete them.
+CREATE TABLE attachment_deletions (
+    storage_key TEXT PRIMARY KEY,
+    queued_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE FUNCTION queue_attachment_deletion() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO attachment_deletions (storage_key) VALUES (OLD.storage_key)
+    ON CONFLICT DO NOTHING;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER attachments_queue_deletion
+AFTER DELETE ON attachments
+FOR EACH ROW EXECUTE FUNCTION queue_attachment_deletion();
@@ -0,0 +1,6 @@
+-- `recurrence` is an RRULE (RFC 5545) such as `FREQ=WEEKLY;BYDAY=MO,FR;COUNT=10`, evaluated in
+-- `recurrence_tz` (UTC when null) and anchored on `due_at`.
+ALTER TABLE tasks
+    ADD COLUMN recurrence TEXT,
+    ADD COLUMN recurrence_tz TEXT,
+    ADD CONSTRAINT tasks_recurrence_needs_due CHECK (recurrence IS NULL OR due_at IS NOT NULL);
@@ -0,0 +1,18 @@
+-- A reminder fires at `remind_at`, or `offset_minutes` before its task's `due_at`, and is
+-- delivered to the user who set it.
+CREATE TABLE reminders (
+    id SERIAL PRIMARY KEY,
+    task_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    remind_at TIMESTAMPTZ,
+    offset_minutes INTEGER CHECK (offset_minutes >= 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    sent_at TIMESTAMPTZ,
+    -- Failed deliveries are retried until `attempts` reaches the worker's limit.
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_attempt_at TIMESTAMPTZ,
+    last_error TEXT,
+    CONSTRAINT reminders_one_time CHECK ((remind_at IS NULL) <> (offset_minutes IS NULL))
+);
+
+CREATE INDEX reminders_pending_idx ON reminders (task_id) WHERE sent_at IS NULL;
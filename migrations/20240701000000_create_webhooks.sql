@@ -0,0 +1,48 @@
+-- Every change to a task is recorded here by the transaction that makes it, so consumers of
+-- the log can't miss a change even if the server stops before passing it on.
+CREATE TABLE task_events (
+    id BIGSERIAL PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    -- Not a foreign key, since deleted tasks keep their events.
+    task_id INTEGER NOT NULL,
+    event TEXT NOT NULL CHECK (event IN ('created', 'updated', 'completed', 'deleted')),
+    -- The task as clients saw it after the change.
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX task_events_workspace_id_idx ON task_events (workspace_id, id);
+CREATE INDEX task_events_created_at_idx ON task_events (created_at);
+
+CREATE TABLE webhooks (
+    id SERIAL PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    -- Kept in the clear, since it signs every delivery.
+    secret TEXT NOT NULL,
+    events TEXT[] NOT NULL,
+    created_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhooks_workspace_id_idx ON webhooks (workspace_id);
+
+-- The outbox: one row per event and subscribed webhook, queued with the event itself and kept
+-- afterwards as the delivery log.
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event_id BIGINT NOT NULL REFERENCES task_events (id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'succeeded', 'failed')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- The outcome of the latest attempt.
+    response_status INTEGER,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at)
+    WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id, id);
@@ -0,0 +1,12 @@
+-- Wakes up event streams on every server sharing the database. The payload is
+-- `<event id>:<workspace id>`; listeners read the events themselves from `task_events`.
+CREATE FUNCTION notify_task_event() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('task_events', NEW.id || ':' || NEW.workspace_id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER task_events_notify
+AFTER INSERT ON task_events
+FOR EACH ROW EXECUTE FUNCTION notify_task_event();
@@ -0,0 +1,37 @@
+-- Extends workspace isolation from tasks to the data hanging off them. Comments, attachments
+-- and reminders are visible exactly when their task is: the policy on `tasks` applies inside
+-- these subqueries too, including its `app.bypass_rls` opt-out. Task events outlive their tasks,
+-- so they follow the workspace instead, like tasks themselves.
+--
+-- Foreign key cascades are not subject to row-level security, so the schema has to keep them
+-- within a workspace on its own (see `tasks_list_id_workspace_id_fkey`). Lists, tags, webhooks
+-- and their deliveries have no policy; their handlers check workspace roles instead.
+ALTER TABLE comments ENABLE ROW LEVEL SECURITY;
+ALTER TABLE comments FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY comments_visible_tasks ON comments
+    USING (EXISTS (SELECT 1 FROM tasks WHERE tasks.id = comments.task_id));
+
+ALTER TABLE attachments ENABLE ROW LEVEL SECURITY;
+ALTER TABLE attachments FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY attachments_visible_tasks ON attachments
+    USING (EXISTS (SELECT 1 FROM tasks WHERE tasks.id = attachments.task_id));
+
+ALTER TABLE reminders ENABLE ROW LEVEL SECURITY;
+ALTER TABLE reminders FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY reminders_visible_tasks ON reminders
+    USING (EXISTS (SELECT 1 FROM tasks WHERE tasks.id = reminders.task_id));
+
+ALTER TABLE task_events ENABLE ROW LEVEL SECURITY;
+ALTER TABLE task_events FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY task_events_workspace_members ON task_events
+    USING (
+        current_setting('app.bypass_rls', TRUE) = 'on'
+        OR workspace_id IN (
+            SELECT workspace_id FROM workspace_members
+            WHERE user_id = NULLIF(current_setting('app.user_id', TRUE), '')::INTEGER
+        )
+    );
@@ -0,0 +1,9 @@
+-- Streams resume from the last event they sent, but event ids are assigned when events are
+-- inserted, not when they commit, so a later id can become visible before an earlier one. The
+-- id of the inserting transaction lets streams wait for every transaction that could still add
+-- an earlier event to finish: those before the oldest transaction still running have. Events
+-- from before this migration are long committed, and get 0.
+ALTER TABLE task_events ADD COLUMN txid BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE task_events ALTER COLUMN txid SET DEFAULT pg_current_xact_id()::TEXT::BIGINT;
+
+CREATE INDEX task_events_txid_idx ON task_events (txid, id);
@@ -0,0 +1,125 @@
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::auth::{generate_token, hash_token, AuthUser};
+use crate::error::{AppError, Path};
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+
+/// Marks a bearer token as an API key rather than a session token.
+pub const API_KEY_PREFIX: &str = "tdk_";
+
+// Enough of the key to tell keys apart in listings without weakening it.
+const DISPLAY_PREFIX_LENGTH: usize = API_KEY_PREFIX.len() + 6;
+
+#[derive(Serialize)]
+pub struct ApiKeyRow {
+    pub id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// Managing keys needs a session or access token, so a leaked key can't mint more keys.
+pub async fn get_api_keys(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+) -> Result<(StatusCode, String), AppError> {
+    user.require_sign_in()?;
+    let rows = sqlx::query_as!(
+        ApiKeyRow,
+        "SELECT id, name, prefix, scope, created_at, last_used_at FROM api_keys
+        WHERE user_id = $1
+        ORDER BY id",
+        user.id
+    )
+    .fetch_all(&db_pool)
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    Read,
+    ReadWrite,
+}
+
+impl ApiKeyScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::ReadWrite => "read_write",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scope: ApiKeyScope,
+}
+
+impl Validate for CreateApiKeyRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.name_max_length);
+        validator.finish()
+    }
+}
+
+// The key itself is only returned here; afterwards only its prefix is shown.
+pub async fn create_api_key(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Valid(request): Valid<CreateApiKeyRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    user.require_sign_in()?;
+    let key = format!("{API_KEY_PREFIX}{}", generate_token());
+    let row = sqlx::query_as!(
+        ApiKeyRow,
+        "INSERT INTO api_keys (user_id, name, prefix, key_hash, scope)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, name, prefix, scope, created_at, last_used_at",
+        user.id,
+        request.name,
+        &key[..DISPLAY_PREFIX_LENGTH],
+        hash_token(&key),
+        request.scope.as_str()
+    )
+    .fetch_one(&db_pool)
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": { "key": key, "api_key": row } }).to_string(),
+    ))
+}
+
+pub async fn delete_api_key(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    user.require_sign_in()?;
+    let result = sqlx::query!(
+        "DELETE FROM api_keys WHERE id = $1 AND user_id = $2",
+        id,
+        user.id
+    )
+    .execute(&db_pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("api_key"));
+    }
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
@@ -0,0 +1,621 @@
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        Multipart, State,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::auth::{generate_token, AuthUser};
+use crate::error::{AppError, Path};
+use crate::storage::{self, Storage};
+use crate::tasks::authorize_task;
+use crate::workspaces::Role;
+
+const FILE_NAME_MAX_LENGTH: usize = 255;
+
+// How much of a file is read ahead to tell its type.
+const SNIFF_LENGTH: usize = 8192;
+
+// How long an upload has to be recorded before its file is treated as orphaned.
+const UPLOAD_GRACE_MINUTES: i32 = 60;
+
+#[derive(Clone)]
+pub struct AttachmentConfig {
+    pub storage: Arc<dyn Storage>,
+    /// The largest file accepted, in bytes.
+    pub max_size: usize,
+}
+
+impl AttachmentConfig {
+    pub fn from_env() -> Self {
+        Self {
+            storage: storage::from_env(),
+            max_size: std::env::var("ATTACHMENT_MAX_BYTES").map_or(25 * 1024 * 1024, |value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("ATTACHMENT_MAX_BYTES must be a whole number."))
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AttachmentRow {
+    pub id: i32,
+    pub task_id: i32,
+    pub uploader_id: Option<i32>,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    #[serde(skip)]
+    pub storage_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn get_attachments(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(task_id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Viewer).await?;
+    let rows = sqlx::query_as!(
+        AttachmentRow,
+        "SELECT * FROM attachments WHERE task_id = $1 ORDER BY id",
+        task_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+// Keeps only the last path segment of the name the client sent, without control characters.
+fn clean_file_name(name: Option<&str>) -> String {
+    let name = name
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(FILE_NAME_MAX_LENGTH)
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "file".to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+// The client's Content-Type is ignored; files are typed by the magic bytes at the start of
+// `head`, and anything unrecognized is served as plain text or opaque bytes. `head` is cut off
+// after `SNIFF_LENGTH` bytes, possibly in the middle of a character.
+fn sniff_content_type(head: &[u8]) -> &'static str {
+    let is_text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() >= SNIFF_LENGTH,
+    };
+    match infer::get(head) {
+        Some(kind) => kind.mime_type(),
+        None if is_text => "text/plain",
+        None => "application/octet-stream",
+    }
+}
+
+// Ends the upload stream once the file passes `max_size`.
+#[derive(Debug)]
+struct FileTooLarge;
+
+impl fmt::Display for FileTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the file is too large")
+    }
+}
+
+impl std::error::Error for FileTooLarge {}
+
+fn file_too_large(max_size: usize) -> AppError {
+    AppError::PayloadTooLarge(
+        "file_too_large",
+        format!("files can be at most {max_size} bytes"),
+    )
+}
+
+// Errors reading the upload reach storage wrapped in `io::Error`, and come back out the same way.
+fn upload_error(e: io::Error, max_size: usize) -> AppError {
+    let message = e.to_string();
+    let Some(inner) = e.into_inner() else {
+        return AppError::Internal(message);
+    };
+    if inner.is::<FileTooLarge>() {
+        return file_too_large(max_size);
+    }
+    match inner.downcast::<MultipartError>() {
+        Ok(e) => AppError::from(*e),
+        Err(_) => AppError::Internal(message),
+    }
+}
+
+/// Takes the first file part of a `multipart/form-data` body; other parts are ignored.
+pub async fn create_attachment(
+    State(db_pool): State<PgPool>,
+    State(config): State<AttachmentConfig>,
+    user: AuthUser,
+    Path(task_id): Path<i32>,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut multipart = multipart?;
+    // Checked before reading the body, so unauthorized callers can't make the server buffer it.
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Editor).await?;
+    tx.commit().await?;
+
+    let mut field = loop {
+        match multipart.next_field().await? {
+            Some(field) if field.file_name().is_some() => break field,
+            Some(_) => continue,
+            None => {
+                return Err(AppError::BadRequest(
+                    "missing_file",
+                    "the request has no file part".to_owned(),
+                ))
+            }
+        }
+    };
+    let file_name = clean_file_name(field.file_name());
+    let mut head = Vec::new();
+    let mut complete = false;
+    while head.len() < SNIFF_LENGTH {
+        let Some(chunk) = field.chunk().await? else {
+            complete = true;
+            break;
+        };
+        if head.len() + chunk.len() > config.max_size {
+            return Err(file_too_large(config.max_size));
+        }
+        head.extend_from_slice(&chunk);
+    }
+    let content_type = sniff_content_type(&head);
+
+    let storage_key = format!("tasks/{task_id}/{}", generate_token());
+    // Queued for deletion before it is stored, and unqueued with the row that records it, so the
+    // file is removed if the upload is never recorded, however the request ends.
+    sqlx::query!(
+        "INSERT INTO attachment_deletions (storage_key, queued_at)
+        VALUES ($1, now() + make_interval(mins => $2))",
+        storage_key,
+        UPLOAD_GRACE_MINUTES
+    )
+    .execute(&db_pool)
+    .await?;
+    // The rest of the file goes to storage as it arrives, and is cut off once it is too large.
+    // A field is not read again once it has ended.
+    let size = AtomicUsize::new(head.len());
+    let rest = if complete {
+        stream::empty().left_stream()
+    } else {
+        let rest = field.map(|chunk| {
+            let chunk = chunk.map_err(io::Error::other)?;
+            if size.fetch_add(chunk.len(), Ordering::Relaxed) + chunk.len() > config.max_size {
+                return Err(io::Error::other(FileTooLarge));
+            }
+            Ok(chunk)
+        });
+        rest.right_stream()
+    };
+    let data = stream::once(async { Ok(Bytes::from(head)) }).chain(rest);
+    config
+        .storage
+        .put(&storage_key, content_type, Box::pin(data))
+        .await
+        .map_err(|e| upload_error(e, config.max_size))?;
+    let size = size.into_inner() as i64;
+
+    let mut tx = user.begin(&db_pool).await?;
+    // The task may have been deleted or moved while the file was uploading.
+    authorize_task(&mut tx, user.id, task_id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        AttachmentRow,
+        "INSERT INTO attachments (task_id, uploader_id, file_name, content_type, size, storage_key)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *",
+        task_id,
+        user.id,
+        file_name,
+        content_type,
+        size,
+        storage_key
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM attachment_deletions WHERE storage_key = $1",
+        storage_key
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Builds a `Content-Disposition` value that keeps non-ASCII names intact (RFC 6266).
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut encoded = String::new();
+    for byte in file_name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Streams the file from storage rather than loading it into memory.
+pub async fn get_attachment(
+    State(db_pool): State<PgPool>,
+    State(config): State<AttachmentConfig>,
+    user: AuthUser,
+    Path((task_id, id)): Path<(i32, i32)>,
+) -> Result<Response, AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Viewer).await?;
+    let row = sqlx::query_as!(
+        AttachmentRow,
+        "SELECT * FROM attachments WHERE id = $1 AND task_id = $2",
+        id,
+        task_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("attachment"))?;
+    tx.commit().await?;
+
+    let stream = config
+        .storage
+        .get(&row.storage_key)
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => AppError::NotFound("attachment"),
+            _ => AppError::Internal(e.to_string()),
+        })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, row.content_type),
+            (header::CONTENT_LENGTH, row.size.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(&row.file_name),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+// The file is removed from storage by `remove_deleted_files` once the row is gone.
+pub async fn delete_attachment(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((task_id, id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Editor).await?;
+    let result = sqlx::query!(
+        "DELETE FROM attachments WHERE id = $1 AND task_id = $2",
+        id,
+        task_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("attachment"));
+    }
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+/// Removes files from storage whose attachments were deleted, or whose uploads were never
+/// recorded, polling every `interval`. Keys whose removal fails stay queued and are retried on
+/// the next pass.
+pub async fn remove_deleted_files(db_pool: PgPool, storage: Arc<dyn Storage>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = remove_deleted_files_batch(&db_pool, storage.as_ref()).await {
+            eprintln!("Failed to remove deleted attachment files: {e}");
+        }
+    }
+}
+
+async fn remove_deleted_files_batch(db_pool: &PgPool, storage: &dyn Storage) -> Result<(), String> {
+    let mut tx = db_pool.begin().await.map_err(|e| e.to_string())?;
+    let keys = sqlx::query_scalar!(
+        "SELECT storage_key FROM attachment_deletions
+        WHERE queued_at <= now()
+        ORDER BY queued_at
+        LIMIT 100
+        FOR UPDATE SKIP LOCKED"
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut removed = Vec::new();
+    for key in keys {
+        match storage.delete(&key).await {
+            Ok(()) => removed.push(key),
+            Err(e) => eprintln!("Failed to remove attachment file {key}: {e}"),
+        }
+    }
+    sqlx::query!(
+        "DELETE FROM attachment_deletions WHERE storage_key = ANY($1)",
+        &removed
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use axum::{async_trait, extract::FromRequest, http::Request};
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::storage::{ByteChunks, FileStream};
+    use crate::test_util::{as_user, data, insert_task, insert_user, personal_workspace, status};
+
+    // Keeps files in memory. Deletes a task when a file is stored, as if it had been deleted
+    // while the file was uploading.
+    #[derive(Default)]
+    struct MemoryStorage {
+        files: Mutex<HashMap<String, Bytes>>,
+        delete_task: Option<(PgPool, i32)>,
+    }
+
+    #[async_trait]
+    impl Storage for MemoryStorage {
+        async fn put(
+            &self,
+            key: &str,
+            _content_type: &str,
+            data: ByteChunks<'_>,
+        ) -> io::Result<()> {
+            let chunks: Vec<Bytes> = data.try_collect().await?;
+            let data = Bytes::from(chunks.concat());
+            self.files.lock().unwrap().insert(key.to_owned(), data);
+            if let Some((db_pool, task_id)) = &self.delete_task {
+                sqlx::query!("DELETE FROM tasks WHERE id = $1", task_id)
+                    .execute(db_pool)
+                    .await
+                    .map_err(io::Error::other)?;
+            }
+            Ok(())
+        }
+
+        async fn get(&self, key: &str) -> io::Result<FileStream> {
+            let data = self.files.lock().unwrap().get(key).cloned();
+            let data = data.ok_or(io::ErrorKind::NotFound)?;
+            Ok(Box::pin(stream::once(async { Ok(data) })))
+        }
+
+        async fn delete(&self, key: &str) -> io::Result<()> {
+            self.files.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    async fn upload(
+        db_pool: &PgPool,
+        storage: Arc<MemoryStorage>,
+        user_id: i32,
+        task_id: i32,
+    ) -> StatusCode {
+        let result = upload_file(
+            db_pool,
+            storage,
+            1024,
+            user_id,
+            task_id,
+            b"Remember the milk",
+        );
+        status(result.await)
+    }
+
+    async fn upload_file(
+        db_pool: &PgPool,
+        storage: Arc<MemoryStorage>,
+        max_size: usize,
+        user_id: i32,
+        task_id: i32,
+        content: &[u8],
+    ) -> Result<(StatusCode, String), AppError> {
+        let body = [
+            b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+            \r\n"
+                .as_slice(),
+            content,
+            b"\r\n--boundary--\r\n",
+        ]
+        .concat();
+        // In small chunks arriving one at a time, as a file would over the network.
+        let chunks: Vec<io::Result<Bytes>> = body
+            .chunks(1024)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let request = Request::builder()
+            .header(
+                header::CONTENT_TYPE,
+                "multipart/form-data; boundary=boundary",
+            )
+            .body(Body::from_stream(stream::iter(chunks).enumerate().then(
+                |(i, chunk)| async move {
+                    if i > 0 {
+                        tokio::task::yield_now().await;
+                    }
+                    chunk
+                },
+            )))
+            .unwrap();
+        let multipart = Multipart::from_request(request, &()).await;
+        create_attachment(
+            State(db_pool.clone()),
+            State(AttachmentConfig { storage, max_size }),
+            as_user(user_id),
+            Path(task_id),
+            multipart,
+        )
+        .await
+    }
+
+    #[sqlx::test]
+    async fn unrecorded_uploads_stay_queued_for_deletion(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, user_id).await;
+        let task_ids = [
+            insert_task(&db_pool, workspace_id, "Shop").await,
+            insert_task(&db_pool, workspace_id, "Cook").await,
+        ];
+        let mut conn = db_pool.acquire().await.unwrap();
+
+        let storage = Arc::new(MemoryStorage::default());
+        assert_eq!(
+            upload(&db_pool, storage.clone(), user_id, task_ids[0]).await,
+            StatusCode::OK
+        );
+        let storage_key = sqlx::query_scalar!(
+            "SELECT storage_key FROM attachments WHERE task_id = $1",
+            task_ids[0]
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        assert!(storage.files.lock().unwrap().contains_key(&storage_key));
+        let queued = sqlx::query_scalar!("SELECT storage_key FROM attachment_deletions")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap();
+        assert!(queued.is_empty(), "recorded upload queued for deletion");
+
+        let storage = Arc::new(MemoryStorage {
+            delete_task: Some((db_pool.clone(), task_ids[1])),
+            ..Default::default()
+        });
+        assert_eq!(
+            upload(&db_pool, storage.clone(), user_id, task_ids[1]).await,
+            StatusCode::NOT_FOUND
+        );
+        let stored: Vec<String> = storage.files.lock().unwrap().keys().cloned().collect();
+        assert_eq!(stored.len(), 1);
+        let queued = sqlx::query_scalar!(
+            "SELECT storage_key FROM attachment_deletions WHERE queued_at > now()"
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(queued, stored);
+    }
+
+    #[sqlx::test]
+    async fn uploads_are_limited_while_streaming_and_downloaded_intact(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, user_id).await;
+        let task_id = insert_task(&db_pool, workspace_id, "Shop").await;
+        let storage = Arc::new(MemoryStorage::default());
+        // Past the part read ahead to tell the file's type.
+        let max_size = 2 * SNIFF_LENGTH;
+
+        let too_large = vec![b'a'; max_size + 1];
+        let result = upload_file(
+            &db_pool,
+            storage.clone(),
+            max_size,
+            user_id,
+            task_id,
+            &too_large,
+        );
+        assert_eq!(status(result.await), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(storage.files.lock().unwrap().is_empty());
+
+        let content = vec![b'a'; max_size];
+        let result = upload_file(
+            &db_pool,
+            storage.clone(),
+            max_size,
+            user_id,
+            task_id,
+            &content,
+        );
+        let uploaded = data(result.await);
+        assert_eq!(uploaded["size"], max_size);
+        assert_eq!(uploaded["content_type"], "text/plain");
+
+        let id = uploaded["id"].as_i64().unwrap() as i32;
+        let response = get_attachment(
+            State(db_pool.clone()),
+            State(AttachmentConfig { storage, max_size }),
+            as_user(user_id),
+            Path((task_id, id)),
+        )
+        .await;
+        let Ok(response) = response else {
+            panic!("the attachment could not be downloaded");
+        };
+        let downloaded = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(downloaded, content);
+    }
+
+    #[test]
+    fn sniff_content_type_allows_characters_cut_off_at_the_end() {
+        let mut head = vec![b'a'; SNIFF_LENGTH - 1];
+        head.extend_from_slice(&"é".as_bytes()[..1]);
+        assert_eq!(sniff_content_type(&head), "text/plain");
+        assert_eq!(
+            sniff_content_type(&head[SNIFF_LENGTH - 2..]),
+            "application/octet-stream"
+        );
+    }
+}
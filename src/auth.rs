@@ -0,0 +1,953 @@
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+
+use crate::api_keys::API_KEY_PREFIX;
+use crate::error::{AppError, Json};
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::workspaces::insert_workspace;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 1024;
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Keys for signing and verifying JWT access tokens.
+#[derive(Clone)]
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl JwtKeys {
+    // HS256 signs with `JWT_SECRET`; RS256 signs with the PEM key pair in `JWT_PRIVATE_KEY_FILE`
+    // and `JWT_PUBLIC_KEY_FILE`.
+    fn from_env() -> Self {
+        let algorithm = std::env::var("JWT_ALGORITHM").unwrap_or("HS256".to_owned());
+        match algorithm.as_str() {
+            "HS256" => Self::hs256(&std::env::var("JWT_SECRET").expect("JWT_SECRET must be set.")),
+            "RS256" => {
+                let read_key = |var: &str| {
+                    let path = std::env::var(var).unwrap_or_else(|_| panic!("{var} must be set."));
+                    std::fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {path}: {e}"))
+                };
+                Self {
+                    algorithm: Algorithm::RS256,
+                    encoding: EncodingKey::from_rsa_pem(&read_key("JWT_PRIVATE_KEY_FILE"))
+                        .expect("JWT_PRIVATE_KEY_FILE must hold a PEM RSA private key."),
+                    decoding: DecodingKey::from_rsa_pem(&read_key("JWT_PUBLIC_KEY_FILE"))
+                        .expect("JWT_PUBLIC_KEY_FILE must hold a PEM RSA public key."),
+                }
+            }
+            _ => panic!("JWT_ALGORITHM must be HS256 or RS256."),
+        }
+    }
+
+    pub fn hs256(secret: &str) -> Self {
+        Self {
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub session_ttl: Duration,
+    pub access_token_ttl: Duration,
+    pub refresh_token_ttl: Duration,
+    pub jwt: JwtKeys,
+}
+
+fn env_duration(var: &str, default: i64, unit: fn(i64) -> Duration) -> Duration {
+    unit(std::env::var(var).map_or(default, |value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("{var} must be a whole number."))
+    }))
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        Self {
+            session_ttl: env_duration("SESSION_TTL_HOURS", 24 * 30, Duration::hours),
+            access_token_ttl: env_duration("ACCESS_TOKEN_TTL_MINUTES", 15, Duration::minutes),
+            refresh_token_ttl: env_duration("REFRESH_TOKEN_TTL_HOURS", 24 * 30, Duration::hours),
+            jwt: JwtKeys::from_env(),
+        }
+    }
+}
+
+/// The caller, identified by the bearer token in the `Authorization` header: a JWT access token,
+/// a session token from `/auth/login`, or an API key.
+pub struct AuthUser {
+    pub id: i32,
+    /// Set when the caller authenticated with an API key.
+    pub api_key_id: Option<i32>,
+}
+
+impl AuthUser {
+    /// Starts a transaction that row-level security policies scope to this user, so that
+    /// queries on it only see rows the user may access.
+    pub async fn begin(
+        &self,
+        db_pool: &PgPool,
+    ) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query_scalar!(
+            "SELECT set_config('app.user_id', $1, TRUE)",
+            self.id.to_string()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        Ok(tx)
+    }
+
+    /// Rejects callers using an API key, for endpoints that need an interactive sign-in.
+    pub fn require_sign_in(&self) -> Result<(), AppError> {
+        if self.api_key_id.is_some() {
+            return Err(AppError::Forbidden(
+                "sign_in_required",
+                "this endpoint cannot be used with an API key".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+    // Set on scoped tokens, which access tokens without it can't stand in for, and the reverse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn generate_token() -> String {
+    let mut token_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut token_bytes);
+    URL_SAFE_NO_PAD.encode(token_bytes)
+}
+
+fn invalid_token() -> AppError {
+    AppError::Unauthorized(
+        "invalid_token",
+        "the bearer token is invalid or expired".to_owned(),
+    )
+}
+
+fn decode_token(keys: &JwtKeys, token: &str, validation: &Validation) -> Result<i32, AppError> {
+    let claims = jsonwebtoken::decode::<Claims>(token, &keys.decoding, validation)
+        .map_err(|_| invalid_token())?
+        .claims;
+    claims.sub.parse().map_err(|_| invalid_token())
+}
+
+// Tokens with an audience are refused.
+fn decode_access_token(keys: &JwtKeys, token: &str) -> Result<i32, AppError> {
+    decode_token(keys, token, &Validation::new(keys.algorithm))
+}
+
+fn sign_token(keys: &JwtKeys, claims: &Claims) -> Result<String, AppError> {
+    jsonwebtoken::encode(&Header::new(keys.algorithm), claims, &keys.encoding)
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// Signs a token for `user_id` that only endpoints decoding it for `audience` accept, for
+/// clients that can't send an `Authorization` header and pass it in the URL instead. Returns the
+/// token and when it expires.
+pub fn sign_scoped_token(
+    config: &AuthConfig,
+    user_id: i32,
+    audience: &str,
+    ttl: Duration,
+) -> Result<(String, DateTime<Utc>), AppError> {
+    let now = Utc::now();
+    let expires_at = now + ttl;
+    let claims = Claims {
+        sub: user_id.to_string(),
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+        aud: Some(audience.to_owned()),
+    };
+    Ok((sign_token(&config.jwt, &claims)?, expires_at))
+}
+
+/// Checks a token from `sign_scoped_token` for `audience`, returning the user's id.
+pub fn decode_scoped_token(
+    config: &AuthConfig,
+    token: &str,
+    audience: &str,
+) -> Result<i32, AppError> {
+    let mut validation = Validation::new(config.jwt.algorithm);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["exp", "aud"]);
+    decode_token(&config.jwt, token, &validation)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+    PgPool: FromRef<S>,
+    AuthConfig: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers).ok_or(AppError::Unauthorized(
+            "missing_token",
+            "a bearer token is required".to_owned(),
+        ))?;
+        // Session tokens are plain base64url, so only JWTs contain dots.
+        if token.contains('.') {
+            let id = decode_access_token(&AuthConfig::from_ref(state).jwt, token)?;
+            return Ok(Self {
+                id,
+                api_key_id: None,
+            });
+        }
+        if token.starts_with(API_KEY_PREFIX) {
+            return authenticate_api_key(&PgPool::from_ref(state), &parts.method, token).await;
+        }
+        let id = sqlx::query_scalar!(
+            "SELECT user_id FROM sessions WHERE token_hash = $1 AND expires_at > now()",
+            hash_token(token)
+        )
+        .fetch_optional(&PgPool::from_ref(state))
+        .await?
+        .ok_or_else(invalid_token)?;
+        Ok(Self {
+            id,
+            api_key_id: None,
+        })
+    }
+}
+
+// Read-only keys may only make safe requests.
+async fn authenticate_api_key(
+    db_pool: &PgPool,
+    method: &Method,
+    key: &str,
+) -> Result<AuthUser, AppError> {
+    let row = sqlx::query!(
+        "UPDATE api_keys SET last_used_at = now() WHERE key_hash = $1 RETURNING id, user_id, scope",
+        hash_token(key)
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or_else(invalid_token)?;
+    if row.scope == "read" && !matches!(*method, Method::GET | Method::HEAD) {
+        return Err(AppError::Forbidden(
+            "insufficient_scope",
+            "this API key is read-only".to_owned(),
+        ));
+    }
+    Ok(AuthUser {
+        id: row.user_id,
+        api_key_id: Some(row.id),
+    })
+}
+
+// Argon2 is deliberately slow, so hashing runs off the async worker threads.
+async fn hash_password(password: String) -> Result<String, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.to_string()))?
+    .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+async fn verify_password(password: String, password_hash: String) -> Result<bool, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let password_hash = PasswordHash::new(&password_hash)?;
+        match Argon2::default().verify_password(password.as_bytes(), &password_hash) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(e),
+        }
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.to_string()))?
+    .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct CredentialsRequest {
+    email: String,
+    password: String,
+}
+
+impl Validate for CredentialsRequest {
+    fn validate(&mut self, _rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("email", &mut self.email, MAX_EMAIL_LENGTH);
+        if !self.email.is_empty() && !self.email.contains('@') {
+            validator.fail(
+                "email",
+                "invalid_email",
+                "must be an email address".to_owned(),
+            );
+        }
+        let password_length = self.password.chars().count();
+        if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password_length) {
+            validator.fail(
+                "password",
+                "invalid_length",
+                format!(
+                    "must be between {MIN_PASSWORD_LENGTH} and {MAX_PASSWORD_LENGTH} characters"
+                ),
+            );
+        }
+        validator.finish()
+    }
+}
+
+#[derive(Serialize)]
+struct UserRow {
+    id: i32,
+    email: String,
+}
+
+pub async fn register(
+    State(db_pool): State<PgPool>,
+    Valid(credentials): Valid<CredentialsRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let password_hash = hash_password(credentials.password).await?;
+    let mut tx = db_pool.begin().await?;
+    let row = sqlx::query_as!(
+        UserRow,
+        "INSERT INTO users (email, password_hash) VALUES ($1, $2) RETURNING id, email",
+        credentials.email,
+        password_hash
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    insert_workspace(&mut tx, row.id, "Personal", true).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Verifying against a throwaway hash for unknown emails keeps the response time from revealing
+// which emails are registered.
+async fn dummy_password_hash() -> Result<String, AppError> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    if let Some(hash) = DUMMY_HASH.get() {
+        return Ok(hash.clone());
+    }
+    let hash = hash_password("dummy password".to_owned()).await?;
+    Ok(DUMMY_HASH.get_or_init(|| hash).clone())
+}
+
+// Returns the id of the user with these credentials.
+async fn authenticate(db_pool: &PgPool, credentials: CredentialsRequest) -> Result<i32, AppError> {
+    let user = sqlx::query!(
+        "SELECT id, password_hash FROM users WHERE lower(email) = lower($1)",
+        credentials.email
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    let (user_id, password_hash) = match user {
+        Some(user) => (Some(user.id), user.password_hash),
+        None => (None, dummy_password_hash().await?),
+    };
+    let verified = verify_password(credentials.password, password_hash).await?;
+    user_id.filter(|_| verified).ok_or(AppError::Unauthorized(
+        "invalid_credentials",
+        "email or password is incorrect".to_owned(),
+    ))
+}
+
+pub async fn login(
+    State(db_pool): State<PgPool>,
+    State(config): State<AuthConfig>,
+    Valid(credentials): Valid<CredentialsRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let user_id = authenticate(&db_pool, credentials).await?;
+    let token = generate_token();
+    let expires_at = Utc::now() + config.session_ttl;
+    sqlx::query!(
+        "INSERT INTO sessions (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+        hash_token(&token),
+        user_id,
+        expires_at
+    )
+    .execute(&db_pool)
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": { "token": token, "expires_at": expires_at } })
+            .to_string(),
+    ))
+}
+
+pub async fn logout(
+    State(db_pool): State<PgPool>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, String), AppError> {
+    let token = bearer_token(&headers).ok_or(AppError::Unauthorized(
+        "missing_token",
+        "a bearer token is required".to_owned(),
+    ))?;
+    sqlx::query!(
+        "DELETE FROM sessions WHERE token_hash = $1",
+        hash_token(token)
+    )
+    .execute(&db_pool)
+    .await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+// Signs an access token and stores a new refresh token in `family`.
+async fn issue_tokens(
+    conn: &mut PgConnection,
+    config: &AuthConfig,
+    user_id: i32,
+    family: &str,
+) -> Result<serde_json::Value, AppError> {
+    let now = Utc::now();
+    let access_expires_at = now + config.access_token_ttl;
+    let claims = Claims {
+        sub: user_id.to_string(),
+        iat: now.timestamp(),
+        exp: access_expires_at.timestamp(),
+        aud: None,
+    };
+    let access_token = sign_token(&config.jwt, &claims)?;
+
+    let refresh_token = generate_token();
+    let refresh_expires_at = now + config.refresh_token_ttl;
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (token_hash, family, user_id, expires_at)
+        VALUES ($1, $2, $3, $4)",
+        hash_token(&refresh_token),
+        family,
+        user_id,
+        refresh_expires_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_at": access_expires_at,
+        "refresh_token": refresh_token,
+        "refresh_expires_at": refresh_expires_at,
+    }))
+}
+
+/// Exchanges credentials for a JWT access token and a refresh token.
+pub async fn create_token(
+    State(db_pool): State<PgPool>,
+    State(config): State<AuthConfig>,
+    Valid(credentials): Valid<CredentialsRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let user_id = authenticate(&db_pool, credentials).await?;
+    let mut conn = db_pool.acquire().await?;
+    let tokens = issue_tokens(&mut conn, &config, user_id, &generate_token()).await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": tokens }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+struct RefreshTokenRow {
+    family: String,
+    user_id: i32,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+fn invalid_refresh_token() -> AppError {
+    AppError::Unauthorized(
+        "invalid_refresh_token",
+        "the refresh token is invalid, expired or revoked".to_owned(),
+    )
+}
+
+// Each refresh token works once. Presenting one that was already rotated means it leaked, so the
+// whole family is revoked and the client has to sign in again.
+pub async fn refresh_token(
+    State(db_pool): State<PgPool>,
+    State(config): State<AuthConfig>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = db_pool.begin().await?;
+    let row = sqlx::query_as!(
+        RefreshTokenRow,
+        "SELECT family, user_id, expires_at, revoked_at FROM refresh_tokens
+        WHERE token_hash = $1
+        FOR UPDATE",
+        hash_token(&request.refresh_token)
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid_refresh_token)?;
+
+    if row.revoked_at.is_some() {
+        revoke_family(&mut tx, &row.family).await?;
+        tx.commit().await?;
+        return Err(invalid_refresh_token());
+    }
+    if row.expires_at <= Utc::now() {
+        return Err(invalid_refresh_token());
+    }
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE token_hash = $1",
+        hash_token(&request.refresh_token)
+    )
+    .execute(&mut *tx)
+    .await?;
+    let tokens = issue_tokens(&mut tx, &config, row.user_id, &row.family).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": tokens }).to_string(),
+    ))
+}
+
+async fn revoke_family(conn: &mut PgConnection, family: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE family = $1 AND revoked_at IS NULL",
+        family
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+// Revokes the refresh token and every token rotated from the same sign-in. Access tokens already
+// issued stay valid until they expire.
+pub async fn revoke_token(
+    State(db_pool): State<PgPool>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut conn = db_pool.acquire().await?;
+    let family = sqlx::query_scalar!(
+        "SELECT family FROM refresh_tokens WHERE token_hash = $1",
+        hash_token(&request.refresh_token)
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(invalid_refresh_token)?;
+    revoke_family(&mut conn, &family).await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+    use crate::api_keys::{create_api_key, delete_api_key};
+    use crate::error::Path;
+    use crate::test_util::{as_user, auth_config, body, data, status};
+
+    #[derive(Clone, FromRef)]
+    struct TestState {
+        db_pool: PgPool,
+        auth_config: AuthConfig,
+    }
+
+    const RSA_PRIVATE_KEY: &[u8] = include_bytes!("../testdata/jwt_private.pem");
+    const RSA_PUBLIC_KEY: &[u8] = include_bytes!("../testdata/jwt_public.pem");
+
+    fn rs256_keys() -> JwtKeys {
+        JwtKeys {
+            algorithm: Algorithm::RS256,
+            encoding: EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY).unwrap(),
+            decoding: DecodingKey::from_rsa_pem(RSA_PUBLIC_KEY).unwrap(),
+        }
+    }
+
+    // An access token for user 1 expiring `expires_in` from now.
+    fn sign(algorithm: Algorithm, key: &EncodingKey, expires_in: Duration) -> String {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: "1".to_owned(),
+            iat: now.timestamp(),
+            exp: (now + expires_in).timestamp(),
+            aud: None,
+        };
+        jsonwebtoken::encode(&Header::new(algorithm), &claims, key).unwrap()
+    }
+
+    fn test_state(db_pool: &PgPool) -> TestState {
+        TestState {
+            db_pool: db_pool.clone(),
+            auth_config: auth_config(),
+        }
+    }
+
+    // The `data` of a successful response, or the error code of a failed one.
+    fn outcome(
+        result: Result<(StatusCode, String), AppError>,
+    ) -> Result<serde_json::Value, &'static str> {
+        match result {
+            Err(AppError::Unauthorized(code, _) | AppError::Forbidden(code, _)) => Err(code),
+            result => Ok(data(result)),
+        }
+    }
+
+    // Authenticates a request with this `Authorization` header, returning the user's id or the
+    // error code.
+    async fn authenticate_request(
+        state: &TestState,
+        method: Method,
+        authorization: &str,
+    ) -> Result<i32, &'static str> {
+        let (mut parts, ()) = Request::builder()
+            .method(method)
+            .header(header::AUTHORIZATION, authorization)
+            .body(())
+            .unwrap()
+            .into_parts();
+        match AuthUser::from_request_parts(&mut parts, state).await {
+            Ok(user) => Ok(user.id),
+            Err(AppError::Unauthorized(code, _) | AppError::Forbidden(code, _)) => Err(code),
+            Err(_) => panic!("authentication failed unexpectedly"),
+        }
+    }
+
+    async fn register_user(db_pool: &PgPool, email: &str, password: &str) -> i32 {
+        let registered = register(
+            State(db_pool.clone()),
+            Valid(body(json!({ "email": email, "password": password }))),
+        )
+        .await;
+        data(registered)["id"].as_i64().unwrap() as i32
+    }
+
+    // Returns the session token.
+    async fn log_in(
+        state: &TestState,
+        email: &str,
+        password: &str,
+    ) -> Result<String, &'static str> {
+        let logged_in = login(
+            State(state.db_pool.clone()),
+            State(state.auth_config.clone()),
+            Valid(body(json!({ "email": email, "password": password }))),
+        )
+        .await;
+        outcome(logged_in).map(|data| data["token"].as_str().unwrap().to_owned())
+    }
+
+    #[sqlx::test]
+    async fn login_rejects_wrong_passwords_and_unknown_emails_alike(db_pool: PgPool) {
+        let state = test_state(&db_pool);
+        let user_id = register_user(&db_pool, "alice@example.com", "correct horse").await;
+
+        let wrong_password = log_in(&state, "alice@example.com", "wrong horse").await;
+        let unknown_email = log_in(&state, "bob@example.com", "correct horse").await;
+        assert_eq!(wrong_password, Err("invalid_credentials"));
+        assert_eq!(unknown_email, wrong_password);
+
+        let token = log_in(&state, "Alice@Example.com", "correct horse")
+            .await
+            .unwrap();
+        let bearer = format!("Bearer {token}");
+        assert_eq!(
+            authenticate_request(&state, Method::GET, &bearer).await,
+            Ok(user_id)
+        );
+    }
+
+    #[sqlx::test]
+    async fn sessions_end_on_logout_and_expiry(db_pool: PgPool) {
+        let state = test_state(&db_pool);
+        register_user(&db_pool, "alice@example.com", "correct horse").await;
+
+        let bearer = format!(
+            "Bearer {}",
+            log_in(&state, "alice@example.com", "correct horse")
+                .await
+                .unwrap()
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, bearer.parse().unwrap());
+        let logged_out = logout(State(db_pool.clone()), headers).await;
+        assert!(status(logged_out).is_success());
+        assert_eq!(
+            authenticate_request(&state, Method::GET, &bearer).await,
+            Err("invalid_token")
+        );
+
+        let token = log_in(&state, "alice@example.com", "correct horse")
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE sessions SET expires_at = now() - INTERVAL '1 minute' WHERE token_hash = $1",
+            hash_token(&token)
+        )
+        .execute(&db_pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            authenticate_request(&state, Method::GET, &format!("Bearer {token}")).await,
+            Err("invalid_token")
+        );
+    }
+
+    #[sqlx::test]
+    async fn malformed_bearer_tokens_are_rejected(db_pool: PgPool) {
+        let state = test_state(&db_pool);
+        // Taken for a JWT, an API key and a session token respectively.
+        for token in ["not.a.jwt", "tdk_unknown", "unknown", ""] {
+            assert_eq!(
+                authenticate_request(&state, Method::GET, &format!("Bearer {token}")).await,
+                Err("invalid_token"),
+                "{token:?}"
+            );
+        }
+        assert_eq!(
+            authenticate_request(&state, Method::GET, "Basic YWxpY2U6aG9yc2U=").await,
+            Err("missing_token")
+        );
+        assert_eq!(status(Err(invalid_token())), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn access_tokens_need_the_configured_algorithm_and_key() {
+        let hs256 = JwtKeys::hs256("test secret");
+        let rs256 = rs256_keys();
+        let valid = |keys: &JwtKeys, token: &str| decode_access_token(keys, token).is_ok();
+
+        let hs256_token = sign(Algorithm::HS256, &hs256.encoding, Duration::minutes(15));
+        let rs256_token = sign(Algorithm::RS256, &rs256.encoding, Duration::minutes(15));
+        assert!(valid(&hs256, &hs256_token));
+        assert!(valid(&rs256, &rs256_token));
+        assert!(!valid(&hs256, &rs256_token));
+        assert!(!valid(&rs256, &hs256_token));
+
+        let other_secret = JwtKeys::hs256("other secret");
+        assert!(!valid(
+            &hs256,
+            &sign(
+                Algorithm::HS256,
+                &other_secret.encoding,
+                Duration::minutes(15)
+            )
+        ));
+        // An HS256 token keyed with the public key must not pass as RS256.
+        let public_key_as_secret = EncodingKey::from_secret(RSA_PUBLIC_KEY);
+        assert!(!valid(
+            &rs256,
+            &sign(
+                Algorithm::HS256,
+                &public_key_as_secret,
+                Duration::minutes(15)
+            )
+        ));
+    }
+
+    #[test]
+    fn scoped_tokens_and_access_tokens_are_not_interchangeable() {
+        let config = auth_config();
+        let Ok((scoped, _)) = sign_scoped_token(&config, 1, "feed", Duration::minutes(1)) else {
+            panic!("failed to sign a scoped token");
+        };
+        assert_eq!(decode_scoped_token(&config, &scoped, "feed").ok(), Some(1));
+        assert!(decode_scoped_token(&config, &scoped, "other").is_err());
+        assert!(decode_access_token(&config.jwt, &scoped).is_err());
+
+        let access = sign(Algorithm::HS256, &config.jwt.encoding, Duration::minutes(1));
+        assert!(decode_scoped_token(&config, &access, "feed").is_err());
+        let Ok((expired, _)) = sign_scoped_token(&config, 1, "feed", -Duration::minutes(2)) else {
+            panic!("failed to sign a scoped token");
+        };
+        assert!(decode_scoped_token(&config, &expired, "feed").is_err());
+    }
+
+    #[test]
+    fn expired_access_tokens_are_rejected() {
+        let keys = JwtKeys::hs256("test secret");
+        // Past the default leeway of a minute.
+        let expired = sign(Algorithm::HS256, &keys.encoding, -Duration::minutes(2));
+        assert!(decode_access_token(&keys, &expired).is_err());
+    }
+
+    #[sqlx::test]
+    async fn reused_refresh_tokens_revoke_their_family(db_pool: PgPool) {
+        let state = test_state(&db_pool);
+        let user_id = register_user(&db_pool, "alice@example.com", "correct horse").await;
+        let credentials = json!({ "email": "alice@example.com", "password": "correct horse" });
+        let created = create_token(
+            State(db_pool.clone()),
+            State(state.auth_config.clone()),
+            Valid(body(credentials)),
+        )
+        .await;
+        let first = data(created)["refresh_token"].as_str().unwrap().to_owned();
+        let refresh = |token: &str| {
+            refresh_token(
+                State(db_pool.clone()),
+                State(state.auth_config.clone()),
+                Json(body(json!({ "refresh_token": token }))),
+            )
+        };
+
+        let rotated = outcome(refresh(&first).await).unwrap();
+        let second = rotated["refresh_token"].as_str().unwrap();
+        let bearer = format!("Bearer {}", rotated["access_token"].as_str().unwrap());
+        assert_eq!(
+            authenticate_request(&state, Method::GET, &bearer).await,
+            Ok(user_id)
+        );
+
+        assert_eq!(outcome(refresh(&first).await), Err("invalid_refresh_token"));
+        assert_eq!(outcome(refresh(second).await), Err("invalid_refresh_token"));
+        let unrevoked = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM refresh_tokens WHERE revoked_at IS NULL"#
+        )
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+        assert_eq!(unrevoked, 0);
+    }
+
+    // Returns the key's id and the key itself.
+    async fn create_key(
+        db_pool: &PgPool,
+        user: AuthUser,
+        scope: &str,
+    ) -> Result<(i32, String), &'static str> {
+        let created = create_api_key(
+            State(db_pool.clone()),
+            user,
+            Valid(body(json!({ "name": "CLI", "scope": scope }))),
+        )
+        .await;
+        outcome(created).map(|data| {
+            let id = data["api_key"]["id"].as_i64().unwrap() as i32;
+            (id, data["key"].as_str().unwrap().to_owned())
+        })
+    }
+
+    #[sqlx::test]
+    async fn read_keys_only_make_safe_requests(db_pool: PgPool) {
+        let state = test_state(&db_pool);
+        let user_id = register_user(&db_pool, "alice@example.com", "correct horse").await;
+        let (_, read_key) = create_key(&db_pool, as_user(user_id), "read")
+            .await
+            .unwrap();
+        let (_, write_key) = create_key(&db_pool, as_user(user_id), "read_write")
+            .await
+            .unwrap();
+
+        for method in [Method::GET, Method::HEAD] {
+            let bearer = format!("Bearer {read_key}");
+            assert_eq!(
+                authenticate_request(&state, method, &bearer).await,
+                Ok(user_id)
+            );
+        }
+        for method in [Method::POST, Method::PATCH, Method::PUT, Method::DELETE] {
+            let read = format!("Bearer {read_key}");
+            let write = format!("Bearer {write_key}");
+            assert_eq!(
+                authenticate_request(&state, method.clone(), &read).await,
+                Err("insufficient_scope"),
+                "{method}"
+            );
+            assert_eq!(
+                authenticate_request(&state, method, &write).await,
+                Ok(user_id)
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn revoked_keys_stop_working(db_pool: PgPool) {
+        let state = test_state(&db_pool);
+        let user_id = register_user(&db_pool, "alice@example.com", "correct horse").await;
+        let (id, key) = create_key(&db_pool, as_user(user_id), "read_write")
+            .await
+            .unwrap();
+        let bearer = format!("Bearer {key}");
+        assert_eq!(
+            authenticate_request(&state, Method::GET, &bearer).await,
+            Ok(user_id)
+        );
+
+        let deleted = delete_api_key(State(db_pool.clone()), as_user(user_id), Path(id)).await;
+        assert_eq!(status(deleted), StatusCode::OK);
+        assert_eq!(
+            authenticate_request(&state, Method::GET, &bearer).await,
+            Err("invalid_token")
+        );
+    }
+
+    #[sqlx::test]
+    async fn keys_cannot_manage_keys(db_pool: PgPool) {
+        let user_id = register_user(&db_pool, "alice@example.com", "correct horse").await;
+        let (id, _) = create_key(&db_pool, as_user(user_id), "read_write")
+            .await
+            .unwrap();
+        let with_key = || AuthUser {
+            id: user_id,
+            api_key_id: Some(id),
+        };
+
+        assert_eq!(
+            create_key(&db_pool, with_key(), "read_write").await,
+            Err("sign_in_required")
+        );
+        let deleted = delete_api_key(State(db_pool.clone()), with_key(), Path(id)).await;
+        assert_eq!(status(deleted), StatusCode::FORBIDDEN);
+    }
+}
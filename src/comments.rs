@@ -0,0 +1,215 @@
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use pulldown_cmark::{Options, Parser};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, Path};
+use crate::tasks::authorize_task;
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::workspaces::{require_role, Role};
+
+#[derive(Serialize)]
+pub struct CommentRow {
+    pub id: i32,
+    pub task_id: i32,
+    pub author_id: Option<i32>,
+    pub body: String,
+    pub body_html: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+// Raw HTML in the Markdown passes through the renderer, so the output is sanitized afterwards.
+fn render_markdown(body: &str) -> String {
+    let parser = Parser::new_ext(body, Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    ammonia::clean(&html)
+}
+
+pub async fn get_comments(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(task_id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Viewer).await?;
+    let rows = sqlx::query_as!(
+        CommentRow,
+        "SELECT * FROM comments WHERE task_id = $1 ORDER BY id",
+        task_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct CommentRequest {
+    body: String,
+}
+
+impl Validate for CommentRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("body", &mut self.body, rules.comment_max_length);
+        validator.finish()
+    }
+}
+
+pub async fn create_comment(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(task_id): Path<i32>,
+    Valid(comment): Valid<CommentRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        CommentRow,
+        "INSERT INTO comments (task_id, author_id, body, body_html)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *",
+        task_id,
+        user.id,
+        comment.body,
+        render_markdown(&comment.body)
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Loads a comment of a task the user can see, along with the task's workspace id.
+async fn fetch_comment(
+    conn: &mut PgConnection,
+    user_id: i32,
+    task_id: i32,
+    id: i32,
+) -> Result<(i32, CommentRow), AppError> {
+    let workspace_id = authorize_task(conn, user_id, task_id, Role::Viewer).await?;
+    let row = sqlx::query_as!(
+        CommentRow,
+        "SELECT * FROM comments WHERE id = $1 AND task_id = $2",
+        id,
+        task_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("comment"))?;
+    Ok((workspace_id, row))
+}
+
+// Only the author can edit a comment.
+pub async fn update_comment(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((task_id, id)): Path<(i32, i32)>,
+    Valid(comment): Valid<CommentRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let (_, existing) = fetch_comment(&mut tx, user.id, task_id, id).await?;
+    if existing.author_id != Some(user.id) {
+        return Err(AppError::Forbidden(
+            "not_author",
+            "only the author can edit a comment".to_owned(),
+        ));
+    }
+    let row = sqlx::query_as!(
+        CommentRow,
+        "UPDATE comments SET body = $2, body_html = $3, updated_at = now()
+        WHERE id = $1
+        RETURNING *",
+        id,
+        comment.body,
+        render_markdown(&comment.body)
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// The author and the workspace's owners can delete a comment.
+pub async fn delete_comment(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((task_id, id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let (workspace_id, existing) = fetch_comment(&mut tx, user.id, task_id, id).await?;
+    if existing.author_id != Some(user.id) {
+        require_role(&mut tx, user.id, workspace_id, Role::Owner).await?;
+    }
+    sqlx::query!("DELETE FROM comments WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{as_user, body, data, insert_task, insert_team, insert_user, status};
+
+    #[test]
+    fn render_markdown_strips_scripts_and_event_handlers() {
+        let html = render_markdown(
+            "**Hi** <script>alert(1)</script>\n\n\
+            [link](javascript:alert(2)) <a href=\"javascript:alert(3)\">raw</a>\n\n\
+            <img src=\"x.png\" onerror=\"alert(4)\">",
+        );
+        assert!(html.contains("<strong>Hi</strong>"), "{html}");
+        assert!(html.contains("<img src=\"x.png\""), "{html}");
+        for unsafe_html in ["<script", "alert(1)", "javascript:", "onerror"] {
+            assert!(!html.contains(unsafe_html), "{unsafe_html} in {html}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn only_the_author_can_edit(db_pool: PgPool) {
+        let owner = insert_user(&db_pool, "owner@example.com").await;
+        let author = insert_user(&db_pool, "author@example.com").await;
+        let workspace_id = insert_team(&db_pool, owner, &[(author, Role::Editor)]).await;
+        let task_id = insert_task(&db_pool, workspace_id, "Shop").await;
+        let created = create_comment(
+            State(db_pool.clone()),
+            as_user(author),
+            Path(task_id),
+            Valid(body(json!({ "body": "Milk" }))),
+        )
+        .await;
+        let id = data(created)["id"].as_i64().unwrap() as i32;
+
+        let edit = |user_id, text: &str| {
+            update_comment(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Path((task_id, id)),
+                Valid(body(json!({ "body": text }))),
+            )
+        };
+        assert_eq!(status(edit(owner, "Eggs").await), StatusCode::FORBIDDEN);
+        let edited = data(edit(author, "Milk and *eggs*").await);
+        assert_eq!(edited["body_html"], "<p>Milk and <em>eggs</em></p>\n");
+    }
+}
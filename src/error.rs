@@ -0,0 +1,209 @@
+use std::sync::OnceLock;
+
+use axum::{
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{JsonRejection, PathRejection},
+        FromRequest, FromRequestParts,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::QueryRejection;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    /// RFC 7807 `application/problem+json` bodies.
+    Problem,
+    /// The original `{ "success": false, "message": ... }` envelope, kept while clients migrate.
+    Legacy,
+}
+
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+/// Selects the error body format for the lifetime of the process. Defaults to `Problem`.
+pub fn set_error_format(format: ErrorFormat) {
+    let _ = ERROR_FORMAT.set(format);
+}
+
+#[derive(Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+pub enum AppError {
+    /// Malformed request, with a stable error code and a human-readable detail.
+    BadRequest(&'static str, String),
+    /// Missing or invalid credentials, with a stable error code and a detail.
+    Unauthorized(&'static str, String),
+    /// Authenticated, but not allowed to do this, with a stable error code and a detail.
+    Forbidden(&'static str, String),
+    /// The named resource (e.g. `"task"`) does not exist.
+    NotFound(&'static str),
+    /// The request conflicts with existing data, with a stable error code and a detail.
+    Conflict(&'static str, String),
+    /// The request body is larger than allowed, with a stable error code and a detail.
+    PayloadTooLarge(&'static str, String),
+    /// Well-formed request whose fields failed validation.
+    Validation(Vec<FieldError>),
+    Internal(String),
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let mut errors = Vec::new();
+        let (status, code, detail) = match self {
+            Self::BadRequest(code, detail) => (StatusCode::BAD_REQUEST, code.to_owned(), detail),
+            Self::Unauthorized(code, detail) => (StatusCode::UNAUTHORIZED, code.to_owned(), detail),
+            Self::Forbidden(code, detail) => (StatusCode::FORBIDDEN, code.to_owned(), detail),
+            Self::NotFound(resource) => (
+                StatusCode::NOT_FOUND,
+                format!("{resource}_not_found"),
+                format!("{resource} not found"),
+            ),
+            Self::Conflict(code, detail) => (StatusCode::CONFLICT, code.to_owned(), detail),
+            Self::PayloadTooLarge(code, detail) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, code.to_owned(), detail)
+            }
+            Self::Validation(field_errors) => {
+                errors = field_errors;
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "validation_failed".to_owned(),
+                    "one or more fields are invalid".to_owned(),
+                )
+            }
+            Self::Internal(message) => {
+                // Internal details are logged but never sent to clients.
+                eprintln!("Internal error: {message}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal_error".to_owned(),
+                    "internal server error".to_owned(),
+                )
+            }
+        };
+
+        let mut response = match ERROR_FORMAT.get().copied().unwrap_or(ErrorFormat::Problem) {
+            ErrorFormat::Problem => {
+                let mut body = json!({
+                    "type": format!("/problems/{code}"),
+                    "title": status.canonical_reason().unwrap_or_default(),
+                    "status": status.as_u16(),
+                    "detail": detail,
+                    "code": code,
+                });
+                if !errors.is_empty() {
+                    body["errors"] = json!(errors);
+                }
+                (
+                    status,
+                    [(header::CONTENT_TYPE, "application/problem+json")],
+                    body.to_string(),
+                )
+                    .into_response()
+            }
+            ErrorFormat::Legacy => {
+                let mut body = json!({ "success": false, "message": detail });
+                if !errors.is_empty() {
+                    body["errors"] = json!(errors);
+                }
+                (status, body.to_string()).into_response()
+            }
+        };
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+        }
+        response
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        if let sqlx::Error::RowNotFound = e {
+            return Self::NotFound("resource");
+        }
+        // See https://www.postgresql.org/docs/current/errcodes-appendix.html.
+        let code = e
+            .as_database_error()
+            .and_then(|db_error| db_error.code())
+            .unwrap_or_default();
+        match code.as_ref() {
+            "23505" => Self::Conflict("already_exists", "resource already exists".to_owned()),
+            "23503" => Self::Conflict(
+                "reference_conflict",
+                "resource is referenced by or references missing data".to_owned(),
+            ),
+            // Raised by row-level security when a write would leave the caller's workspaces.
+            "42501" => Self::Forbidden(
+                "forbidden",
+                "you are not allowed to access this resource".to_owned(),
+            ),
+            "23502" | "23514" => Self::BadRequest(
+                "constraint_violation",
+                "value violates a constraint".to_owned(),
+            ),
+            code if code.starts_with("22") => {
+                Self::BadRequest("invalid_value", "invalid value".to_owned())
+            }
+            _ => Self::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::BadRequest("invalid_body", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::BadRequest("invalid_query", rejection.to_string())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::BadRequest("invalid_path", rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for AppError {
+    fn from(rejection: MultipartRejection) -> Self {
+        Self::BadRequest("invalid_body", rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(e: MultipartError) -> Self {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return Self::PayloadTooLarge(
+                "payload_too_large",
+                "request body is too large".to_owned(),
+            );
+        }
+        Self::BadRequest("invalid_body", e.body_text())
+    }
+}
+
+// Wrappers around the axum extractors that report rejections through `AppError`.
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum_extra::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
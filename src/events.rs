@@ -0,0 +1,554 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    fmt,
+    str::FromStr,
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{postgres::PgListener, PgConnection, PgPool};
+use tokio::sync::broadcast;
+
+use crate::auth::{decode_scoped_token, sign_scoped_token, AuthConfig, AuthUser};
+use crate::error::{AppError, Query};
+
+// The channel `task_events` inserts are announced on, by a trigger.
+const NOTIFY_CHANNEL: &str = "task_events";
+
+// Events sent per round trip to a stream, and how often streams look for events regardless of
+// notifications, in case some were missed while the listener reconnected. Events held back for
+// a transaction still running are looked for again sooner, since its end may not be announced.
+const STREAM_BATCH_SIZE: i64 = 100;
+const STREAM_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const STREAM_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+const LISTEN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// Tokens for opening streams from browsers. They're passed in the URL, where they may end up in
+// logs, so they only open streams and don't last long; open streams outlive them.
+const STREAM_TOKEN_AUDIENCE: &str = "task_events";
+const STREAM_TOKEN_TTL_SECONDS: i64 = 60;
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskEvent {
+    Created,
+    Updated,
+    Completed,
+    Deleted,
+}
+
+impl TaskEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Completed => "completed",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+/// Appends a change to the task event log and queues a delivery for every webhook of the
+/// workspace subscribed to it. Runs in the caller's transaction, so the event is recorded
+/// exactly when the change is.
+pub async fn record_task_event(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    task_id: i32,
+    event: TaskEvent,
+    payload: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "WITH event AS (
+            INSERT INTO task_events (workspace_id, task_id, event, payload)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+        )
+        INSERT INTO webhook_deliveries (webhook_id, event_id)
+        SELECT webhooks.id, event.id FROM webhooks, event
+        WHERE webhooks.workspace_id = $1 AND $3 = ANY (webhooks.events)",
+        workspace_id,
+        task_id,
+        event.as_str(),
+        payload
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Announces new events to the streams open on this server. Notices only say where to look;
+/// streams read the events themselves, and only those their user may see.
+#[derive(Clone)]
+pub struct TaskEventHub {
+    sender: broadcast::Sender<EventNotice>,
+}
+
+#[derive(Clone, Copy)]
+struct EventNotice {
+    workspace_id: i32,
+}
+
+impl Default for TaskEventHub {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(1024).0,
+        }
+    }
+}
+
+/// Relays the database's notifications of new events to `hub`, so streams hear about changes
+/// made through any server sharing the database.
+pub async fn listen_for_task_events(db_pool: PgPool, hub: TaskEventHub) {
+    loop {
+        if let Err(e) = relay_notifications(&db_pool, &hub).await {
+            eprintln!("Failed to listen for task events: {e}");
+        }
+        tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+    }
+}
+
+async fn relay_notifications(db_pool: &PgPool, hub: &TaskEventHub) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(db_pool).await?;
+    listener.listen(NOTIFY_CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        // The payload is `<event id>:<workspace id>`.
+        let workspace_id = notification
+            .payload()
+            .split_once(':')
+            .and_then(|(_, workspace_id)| workspace_id.parse().ok());
+        if let Some(workspace_id) = workspace_id {
+            // Fails only when no stream is open, which is fine.
+            let _ = hub.sender.send(EventNotice { workspace_id });
+        }
+    }
+}
+
+// Where a stream is in the event log: events are sent in order of the transaction that
+// recorded them, then of id. This is the order they become final in, unlike ids alone, which
+// are assigned before their transactions commit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Position {
+    txid: i64,
+    id: i64,
+}
+
+impl FromStr for Position {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (txid, id) = value.trim().split_once('-').ok_or(())?;
+        Ok(Self {
+            txid: txid.parse().map_err(|_| ())?,
+            id: id.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.txid, self.id)
+    }
+}
+
+struct StreamedEvent {
+    txid: i64,
+    id: i64,
+    event: String,
+    workspace_id: i32,
+    task_id: i32,
+    payload: serde_json::Value,
+    created_at: DateTime<Utc>,
+}
+
+impl StreamedEvent {
+    fn into_sse(self) -> Event {
+        let data = json!({
+            "id": self.id,
+            "event": format!("task.{}", self.event),
+            "workspace_id": self.workspace_id,
+            "task_id": self.task_id,
+            "created_at": self.created_at,
+            "data": self.payload,
+        });
+        let position = Position {
+            txid: self.txid,
+            id: self.id,
+        };
+        Event::default()
+            .id(position.to_string())
+            .event(format!("task.{}", self.event))
+            .data(data.to_string())
+    }
+}
+
+struct EventStream {
+    db_pool: PgPool,
+    notices: broadcast::Receiver<EventNotice>,
+    user: AuthUser,
+    position: Position,
+    workspace_ids: HashSet<i32>,
+    pending: VecDeque<StreamedEvent>,
+}
+
+impl EventStream {
+    async fn next_event(&mut self) -> Result<Option<Event>, sqlx::Error> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                self.position = Position {
+                    txid: event.txid,
+                    id: event.id,
+                };
+                return Ok(Some(event.into_sse()));
+            }
+            let (events, held_back) = self.fetch().await?;
+            self.pending = events.into();
+            if !self.pending.is_empty() {
+                continue;
+            }
+            // Waits for a notice about one of the user's workspaces, or for the next poll. Notices
+            // about other workspaces don't put the poll off, but may be about one the user has
+            // joined since connecting.
+            let interval = if held_back {
+                STREAM_RECHECK_INTERVAL
+            } else {
+                STREAM_POLL_INTERVAL
+            };
+            let deadline = tokio::time::Instant::now() + interval;
+            loop {
+                match tokio::time::timeout_at(deadline, self.notices.recv()).await {
+                    Ok(Ok(notice)) => {
+                        if !self.workspace_ids.contains(&notice.workspace_id) {
+                            self.workspace_ids = self.load_workspace_ids().await?;
+                        }
+                        if self.workspace_ids.contains(&notice.workspace_id) {
+                            break;
+                        }
+                    }
+                    Ok(Err(broadcast::error::RecvError::Lagged(_))) => break,
+                    Ok(Err(broadcast::error::RecvError::Closed)) => return Ok(None),
+                    Err(_) => {
+                        self.workspace_ids = self.load_workspace_ids().await?;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Reads the events after the last one sent, from every workspace the user is a member of
+    // now, so that joining or leaving a workspace takes effect without reconnecting. Events of
+    // transactions no older than the oldest one still running are held back, since that one
+    // could yet record events before them; whether any were is returned alongside.
+    async fn fetch(&self) -> Result<(Vec<StreamedEvent>, bool), sqlx::Error> {
+        let mut tx = self.user.begin(&self.db_pool).await?;
+        // Taken before reading, so every transaction before it has ended by the time we read.
+        let oldest_running = oldest_running_txid(&mut tx).await?;
+        let mut events = sqlx::query_as!(
+            StreamedEvent,
+            "SELECT txid, id, event, workspace_id, task_id, payload, created_at FROM task_events
+            WHERE (txid, id) > ($1, $2) AND workspace_id IN (
+                SELECT workspace_id FROM workspace_members WHERE user_id = $3
+            )
+            ORDER BY txid, id
+            LIMIT $4",
+            self.position.txid,
+            self.position.id,
+            self.user.id,
+            STREAM_BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        let settled = events
+            .iter()
+            .take_while(|event| event.txid < oldest_running)
+            .count();
+        let held_back = settled < events.len();
+        events.truncate(settled);
+        Ok((events, held_back))
+    }
+
+    async fn load_workspace_ids(&self) -> Result<HashSet<i32>, sqlx::Error> {
+        let workspace_ids = sqlx::query_scalar!(
+            "SELECT workspace_id FROM workspace_members WHERE user_id = $1",
+            self.user.id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(workspace_ids.into_iter().collect())
+    }
+}
+
+// The id of the oldest transaction still running, before which all transactions have ended.
+async fn oldest_running_txid(conn: &mut PgConnection) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT AS "txid!""#
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Issues a token that opens `/tasks/events` for a minute, passed as `?token=`. Browsers'
+/// `EventSource` can't send an `Authorization` header, so it can't use the caller's own token.
+pub async fn create_task_events_token(
+    State(config): State<AuthConfig>,
+    user: AuthUser,
+) -> Result<(StatusCode, String), AppError> {
+    let (token, expires_at) = sign_scoped_token(
+        &config,
+        user.id,
+        STREAM_TOKEN_AUDIENCE,
+        chrono::Duration::seconds(STREAM_TOKEN_TTL_SECONDS),
+    )?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": { "token": token, "expires_at": expires_at } })
+            .to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct TaskEventsQuery {
+    token: Option<String>,
+    last_event_id: Option<String>,
+}
+
+/// Streams changes to the caller's tasks as server-sent events, named after the change (e.g.
+/// `task.updated`) and carrying the same body as webhook deliveries. Each event's id is its
+/// position in the event log, as `<transaction>-<event id>`: clients reconnecting with
+/// `Last-Event-ID` get the events they missed, as far back as events are kept, and new clients
+/// start with the changes not yet final when they connect. Events wait for transactions that
+/// started before them to end, so a long-running transaction delays streams until it does.
+///
+/// Instead of a bearer token, the caller can be identified by a `token` from
+/// `/tasks/events/token`. `EventSource` reconnects with the same URL, so once that token expires
+/// the client opens a new stream with a new token, passing the last event id it saw as
+/// `last_event_id`.
+pub async fn get_task_events(
+    State(db_pool): State<PgPool>,
+    State(config): State<AuthConfig>,
+    State(hub): State<TaskEventHub>,
+    user: Result<AuthUser, AppError>,
+    Query(query): Query<TaskEventsQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let user = match query.token {
+        Some(token) => AuthUser {
+            id: decode_scoped_token(&config, &token, STREAM_TOKEN_AUDIENCE)?,
+            api_key_id: None,
+        },
+        None => user?,
+    };
+    // Subscribes before choosing where to start, so no change in between goes unnoticed.
+    let notices = hub.sender.subscribe();
+    let last_event_id = match headers.get("Last-Event-ID") {
+        Some(value) => Some(value.to_str().ok()),
+        None => query.last_event_id.as_deref().map(Some),
+    };
+    let position = match last_event_id {
+        Some(value) => value
+            .and_then(|value| value.parse::<Position>().ok())
+            .ok_or(AppError::BadRequest(
+                "invalid_last_event_id",
+                "Last-Event-ID must be an event id".to_owned(),
+            ))?,
+        None => Position {
+            txid: oldest_running_txid(&mut *db_pool.acquire().await?).await?,
+            id: 0,
+        },
+    };
+    let mut events = EventStream {
+        db_pool,
+        notices,
+        user,
+        position,
+        workspace_ids: HashSet::new(),
+        pending: VecDeque::new(),
+    };
+    events.workspace_ids = events.load_workspace_ids().await?;
+
+    // On a database error the stream ends, and the client reconnects where it left off.
+    let stream = stream::unfold(events, |mut events| async move {
+        match events.next_event().await {
+            Ok(Some(event)) => Some((Ok(event), events)),
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("Failed to stream task events: {e}");
+                None
+            }
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{as_user, insert_team, insert_user, personal_workspace};
+    use crate::workspaces::Role;
+
+    #[test]
+    fn positions_round_trip() {
+        let position = Position { txid: 812, id: 52 };
+        assert_eq!(position.to_string(), "812-52");
+        assert_eq!(" 812-52 ".parse(), Ok(position));
+        for value in ["52", "812-", "-52", "812-52-1", "a-b"] {
+            assert!(value.parse::<Position>().is_err(), "{value}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn events_wait_for_earlier_transactions(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, user_id).await;
+        let mut conn = db_pool.acquire().await.unwrap();
+        let mut events = EventStream {
+            db_pool: db_pool.clone(),
+            notices: TaskEventHub::default().sender.subscribe(),
+            user: as_user(user_id),
+            position: Position {
+                txid: oldest_running_txid(&mut conn).await.unwrap(),
+                id: 0,
+            },
+            workspace_ids: HashSet::new(),
+            pending: VecDeque::new(),
+        };
+
+        // The first transaction records its event first, but commits last.
+        let mut first = db_pool.begin().await.unwrap();
+        record_task_event(&mut first, workspace_id, 1, TaskEvent::Created, json!({}))
+            .await
+            .unwrap();
+        let mut second = db_pool.begin().await.unwrap();
+        record_task_event(&mut second, workspace_id, 2, TaskEvent::Created, json!({}))
+            .await
+            .unwrap();
+        second.commit().await.unwrap();
+
+        let (fetched, held_back) = events.fetch().await.unwrap();
+        assert!(fetched.is_empty());
+        assert!(held_back);
+
+        first.commit().await.unwrap();
+        // Transactions of other tests running alongside may hold the events back for a while.
+        let mut fetched = Vec::new();
+        for _ in 0..50 {
+            let held_back;
+            (fetched, held_back) = events.fetch().await.unwrap();
+            if !held_back {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let task_ids: Vec<i32> = fetched.iter().map(|event| event.task_id).collect();
+        assert_eq!(task_ids, [1, 2]);
+
+        events.pending = fetched.into();
+        while !events.pending.is_empty() {
+            events.next_event().await.unwrap();
+        }
+        let (fetched, _) = events.fetch().await.unwrap();
+        assert!(fetched.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn notices_of_other_workspaces_do_not_delay_held_back_events(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, user_id).await;
+        let hub = TaskEventHub::default();
+        let mut conn = db_pool.acquire().await.unwrap();
+        let mut events = EventStream {
+            db_pool: db_pool.clone(),
+            notices: hub.sender.subscribe(),
+            user: as_user(user_id),
+            position: Position {
+                txid: oldest_running_txid(&mut conn).await.unwrap(),
+                id: 0,
+            },
+            workspace_ids: HashSet::new(),
+            pending: VecDeque::new(),
+        };
+        events.workspace_ids = events.load_workspace_ids().await.unwrap();
+
+        let mut first = db_pool.begin().await.unwrap();
+        record_task_event(&mut first, workspace_id, 1, TaskEvent::Created, json!({}))
+            .await
+            .unwrap();
+        let mut second = db_pool.begin().await.unwrap();
+        record_task_event(&mut second, workspace_id, 2, TaskEvent::Created, json!({}))
+            .await
+            .unwrap();
+        second.commit().await.unwrap();
+
+        // Notices about someone else's workspace keep arriving faster than the recheck interval.
+        let sender = hub.sender.clone();
+        let noise = tokio::spawn(async move {
+            loop {
+                let _ = sender.send(EventNotice { workspace_id: 0 });
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        });
+        let (_, event) = tokio::join!(
+            async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                first.commit().await.unwrap();
+            },
+            tokio::time::timeout(Duration::from_secs(20), events.next_event()),
+        );
+        noise.abort();
+
+        assert!(matches!(event, Ok(Ok(Some(_)))));
+        let task_ids: Vec<i32> = events.pending.iter().map(|event| event.task_id).collect();
+        assert_eq!(task_ids, [2]);
+    }
+
+    #[sqlx::test]
+    async fn notices_of_joined_workspaces_are_not_dropped(db_pool: PgPool) {
+        let owner = insert_user(&db_pool, "owner@example.com").await;
+        let member = insert_user(&db_pool, "member@example.com").await;
+        let hub = TaskEventHub::default();
+        let mut conn = db_pool.acquire().await.unwrap();
+        let mut events = EventStream {
+            db_pool: db_pool.clone(),
+            notices: hub.sender.subscribe(),
+            user: as_user(member),
+            position: Position {
+                txid: oldest_running_txid(&mut conn).await.unwrap(),
+                id: 0,
+            },
+            workspace_ids: HashSet::new(),
+            pending: VecDeque::new(),
+        };
+        events.workspace_ids = events.load_workspace_ids().await.unwrap();
+
+        // The member joins a workspace after connecting, and a task there changes.
+        let (_, event) = tokio::join!(
+            async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                let workspace_id = insert_team(&db_pool, owner, &[(member, Role::Editor)]).await;
+                let mut tx = db_pool.begin().await.unwrap();
+                record_task_event(&mut tx, workspace_id, 1, TaskEvent::Created, json!({}))
+                    .await
+                    .unwrap();
+                tx.commit().await.unwrap();
+                assert!(hub.sender.send(EventNotice { workspace_id }).is_ok());
+            },
+            // Well before the next poll.
+            tokio::time::timeout(STREAM_POLL_INTERVAL / 3, events.next_event()),
+        );
+
+        assert!(matches!(event, Ok(Ok(Some(_)))));
+    }
+}
@@ -0,0 +1,245 @@
+use axum::{extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, Path, Query};
+use crate::events::TaskEvent;
+use crate::tasks::{deserialize_some, parse_sort, record_events_for_tasks};
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::workspaces::{check_role, personal_workspace_id, require_role, Role};
+
+#[derive(Serialize)]
+pub struct ListRow {
+    pub id: i32,
+    pub workspace_id: i32,
+    pub name: String,
+    pub default_sort: String,
+    pub is_default: bool,
+}
+
+/// Checks that the user has at least `required` in the list's workspace, returning the
+/// workspace. Lists outside the user's workspaces are reported as not found.
+pub async fn authorize_list(
+    conn: &mut PgConnection,
+    user_id: i32,
+    id: i32,
+    required: Role,
+) -> Result<i32, AppError> {
+    let membership = sqlx::query!(
+        r#"SELECT lists.workspace_id AS "workspace_id!", workspace_members.role
+        FROM lists JOIN workspace_members ON workspace_members.workspace_id = lists.workspace_id
+        WHERE lists.id = $1 AND workspace_members.user_id = $2"#,
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("list"))?;
+    check_role(Some(&membership.role), required, "list")?;
+    Ok(membership.workspace_id)
+}
+
+#[derive(Deserialize)]
+pub struct GetListsQuery {
+    workspace_id: Option<i32>,
+}
+
+/// Lists the lists of every workspace the caller belongs to, or of just one.
+pub async fn get_lists(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Query(query): Query<GetListsQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let rows = sqlx::query_as!(
+        ListRow,
+        r#"SELECT id, workspace_id AS "workspace_id!", name, default_sort, is_default FROM lists
+        WHERE workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = $1)
+            AND ($2::INTEGER IS NULL OR workspace_id = $2)
+        ORDER BY id"#,
+        user.id,
+        query.workspace_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+async fn fetch_list(conn: &mut PgConnection, id: i32) -> Result<ListRow, AppError> {
+    sqlx::query_as!(
+        ListRow,
+        r#"SELECT id, workspace_id AS "workspace_id!", name, default_sort, is_default FROM lists
+        WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(AppError::NotFound("list"))
+}
+
+pub async fn get_list(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_list(&mut tx, user.id, id, Role::Viewer).await?;
+    let row = fetch_list(&mut tx, id).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+fn validate_default_sort(validator: &mut Validator, default_sort: &str) {
+    if let Err(message) = parse_sort(Some(default_sort)) {
+        validator.fail("default_sort", "invalid_sort", message);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateListRequest {
+    name: String,
+    default_sort: Option<String>,
+    /// Defaults to the caller's personal workspace.
+    workspace_id: Option<i32>,
+}
+
+impl Validate for CreateListRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.name_max_length);
+        if let Some(default_sort) = &self.default_sort {
+            validate_default_sort(&mut validator, default_sort);
+        }
+        validator.finish()
+    }
+}
+
+pub async fn create_list(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Valid(list): Valid<CreateListRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let workspace_id = match list.workspace_id {
+        Some(workspace_id) => workspace_id,
+        None => personal_workspace_id(&mut tx, user.id).await?,
+    };
+    require_role(&mut tx, user.id, workspace_id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        ListRow,
+        r#"INSERT INTO lists (name, default_sort, workspace_id) VALUES ($1, COALESCE($2, 'id'), $3)
+        RETURNING id, workspace_id AS "workspace_id!", name, default_sort, is_default"#,
+        list.name,
+        list.default_sort,
+        workspace_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// PATCH leaves omitted fields untouched; neither field can be null.
+#[derive(Deserialize)]
+pub struct UpdateListRequest {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    default_sort: Option<String>,
+}
+
+impl Validate for UpdateListRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        if let Some(name) = &mut self.name {
+            validator.text("name", name, rules.name_max_length);
+        }
+        if let Some(default_sort) = &self.default_sort {
+            validate_default_sort(&mut validator, default_sort);
+        }
+        validator.finish()
+    }
+}
+
+pub async fn update_list(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Valid(list): Valid<UpdateListRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_list(&mut tx, user.id, id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        ListRow,
+        r#"UPDATE lists SET name = COALESCE($2, name), default_sort = COALESCE($3, default_sort)
+        WHERE id = $1
+        RETURNING id, workspace_id AS "workspace_id!", name, default_sort, is_default"#,
+        id,
+        list.name,
+        list.default_sort
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("list"))?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Deleting a list deletes its tasks, along with their subtasks in other lists, all of which are
+// in the list's workspace. The default list can't be deleted, since tasks created without a list
+// go there.
+pub async fn delete_list(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_list(&mut tx, user.id, id, Role::Editor).await?;
+    if fetch_list(&mut tx, id).await?.is_default {
+        return Err(AppError::Conflict(
+            "default_list",
+            "the default list cannot be deleted".to_owned(),
+        ));
+    }
+
+    let task_ids = sqlx::query_scalar!(
+        r#"WITH RECURSIVE subtree AS (
+            SELECT id FROM tasks WHERE list_id = $1
+            UNION
+            SELECT tasks.id FROM tasks JOIN subtree ON tasks.parent_id = subtree.id
+        )
+        SELECT id AS "id!" FROM subtree"#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    record_events_for_tasks(&mut tx, TaskEvent::Deleted, &task_ids).await?;
+    let result = sqlx::query!("DELETE FROM lists WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("list"));
+    }
+    tx.commit().await?;
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
@@ -1,18 +1,78 @@
+use std::time::Duration;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing, Json, Router,
+    extract::{DefaultBodyLimit, FromRef},
+    routing, Router,
 };
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool};
 use tokio::net::TcpListener;
 
+use api_keys::{create_api_key, delete_api_key, get_api_keys};
+use attachments::{
+    create_attachment, delete_attachment, get_attachment, get_attachments, remove_deleted_files,
+    AttachmentConfig,
+};
+use auth::{create_token, login, logout, refresh_token, register, revoke_token, AuthConfig};
+use comments::{create_comment, delete_comment, get_comments, update_comment};
+use error::ErrorFormat;
+use events::{create_task_events_token, get_task_events, listen_for_task_events, TaskEventHub};
+use lists::{create_list, delete_list, get_list, get_lists, update_list};
+use reminders::{create_reminder, delete_reminder, deliver_reminders, get_reminders};
+use tags::{create_tag, delete_tag, get_tag, get_tags, update_tag};
+use tasks::{
+    complete_task, create_list_task, create_task, delete_task, get_agenda, get_list_tasks,
+    get_my_tasks, get_overdue_tasks, get_task, get_task_assignments, get_task_occurrences,
+    get_tasks, replace_task, uncomplete_task, update_task,
+};
+use validation::ValidationRules;
+use webhooks::{
+    create_webhook, delete_webhook, deliver_webhooks, get_webhook_deliveries, get_webhooks,
+    update_webhook,
+};
+use workspaces::{
+    add_member, create_workspace, delete_workspace, get_members, get_workspace, get_workspaces,
+    remove_member, update_member, update_workspace,
+};
+
+mod api_keys;
+mod attachments;
+mod auth;
+mod comments;
+mod error;
+mod events;
+mod lists;
+mod notifier;
+mod recurrence;
+mod reminders;
+mod storage;
+mod tags;
+mod tasks;
+#[cfg(test)]
+mod test_util;
+mod validation;
+mod webhooks;
+mod worker;
+mod workspaces;
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Clone, FromRef)]
+struct AppState {
+    db_pool: PgPool,
+    validation_rules: ValidationRules,
+    auth_config: AuthConfig,
+    attachment_config: AttachmentConfig,
+    task_event_hub: TaskEventHub,
+}
+
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().expect("Failed to load .env file.");
     let server_address = std::env::var("SERVER_ADDRESS").unwrap_or("localhost:8080".to_owned());
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+    if std::env::var("LEGACY_ERROR_ENVELOPE").is_ok_and(|value| value == "true") {
+        error::set_error_format(ErrorFormat::Legacy);
+    }
 
     let db_pool = PgPoolOptions::new()
         .max_connections(16)
@@ -20,125 +80,185 @@ async fn main() {
         .await
         .expect("Failed to connect to Postgres.");
 
+    check_schema_version(&db_pool)
+        .await
+        .expect("Failed to check schema version.");
+    MIGRATOR
+        .run(&db_pool)
+        .await
+        .expect("Failed to run migrations.");
+    if std::env::args().any(|arg| arg == "--migrate-only") {
+        return;
+    }
+
     let listener = TcpListener::bind(server_address)
         .await
         .expect("Failed to bind to address.");
 
+    let attachment_config = AttachmentConfig::from_env();
+    tokio::spawn(remove_deleted_files(
+        db_pool.clone(),
+        attachment_config.storage.clone(),
+        Duration::from_secs(60),
+    ));
+    tokio::spawn(deliver_reminders(
+        db_pool.clone(),
+        notifier::from_env(),
+        Duration::from_secs(30),
+    ));
+    // Webhooks may only target public addresses, unless this is set for local development.
+    let allow_private_webhook_targets =
+        std::env::var("WEBHOOK_ALLOW_PRIVATE_TARGETS").is_ok_and(|value| value == "true");
+    tokio::spawn(deliver_webhooks(
+        db_pool.clone(),
+        Duration::from_secs(5),
+        allow_private_webhook_targets,
+    ));
+    let task_event_hub = TaskEventHub::default();
+    tokio::spawn(listen_for_task_events(
+        db_pool.clone(),
+        task_event_hub.clone(),
+    ));
+    // Leaves room for the multipart framing around the file itself.
+    let upload_limit = DefaultBodyLimit::max(attachment_config.max_size + 64 * 1024);
+
     let router = Router::new()
         .route("/", routing::get(|| async { "Hello, World!" }))
+        .route("/auth/register", routing::post(register))
+        .route("/auth/login", routing::post(login))
+        .route("/auth/logout", routing::post(logout))
+        .route("/auth/token", routing::post(create_token))
+        .route("/auth/token/refresh", routing::post(refresh_token))
+        .route("/auth/token/revoke", routing::post(revoke_token))
+        .route("/api-keys", routing::get(get_api_keys).post(create_api_key))
+        .route("/api-keys/:id", routing::delete(delete_api_key))
+        .route("/me/tasks", routing::get(get_my_tasks))
         .route("/tasks", routing::get(get_tasks).post(create_task))
-        .route("/tasks/:id", routing::put(update_task).delete(delete_task))
-        .with_state(db_pool);
+        .route("/tasks/agenda", routing::get(get_agenda))
+        .route("/tasks/events", routing::get(get_task_events))
+        .route(
+            "/tasks/events/token",
+            routing::post(create_task_events_token),
+        )
+        .route("/tasks/overdue", routing::get(get_overdue_tasks))
+        .route(
+            "/tasks/:id",
+            routing::get(get_task)
+                .put(replace_task)
+                .patch(update_task)
+                .delete(delete_task),
+        )
+        .route("/tasks/:id/assignments", routing::get(get_task_assignments))
+        .route(
+            "/tasks/:id/comments",
+            routing::get(get_comments).post(create_comment),
+        )
+        .route(
+            "/tasks/:id/comments/:comment_id",
+            routing::patch(update_comment).delete(delete_comment),
+        )
+        .route(
+            "/tasks/:id/attachments",
+            routing::get(get_attachments)
+                .post(create_attachment)
+                .layer(upload_limit),
+        )
+        .route(
+            "/tasks/:id/attachments/:attachment_id",
+            routing::get(get_attachment).delete(delete_attachment),
+        )
+        .route(
+            "/tasks/:id/reminders",
+            routing::get(get_reminders).post(create_reminder),
+        )
+        .route(
+            "/tasks/:id/reminders/:reminder_id",
+            routing::delete(delete_reminder),
+        )
+        .route("/tasks/:id/occurrences", routing::get(get_task_occurrences))
+        .route("/tasks/:id/complete", routing::post(complete_task))
+        .route("/tasks/:id/uncomplete", routing::post(uncomplete_task))
+        .route("/lists", routing::get(get_lists).post(create_list))
+        .route(
+            "/lists/:id",
+            routing::get(get_list)
+                .patch(update_list)
+                .delete(delete_list),
+        )
+        .route(
+            "/lists/:list_id/tasks",
+            routing::get(get_list_tasks).post(create_list_task),
+        )
+        .route(
+            "/workspaces",
+            routing::get(get_workspaces).post(create_workspace),
+        )
+        .route(
+            "/workspaces/:id",
+            routing::get(get_workspace)
+                .patch(update_workspace)
+                .delete(delete_workspace),
+        )
+        .route(
+            "/workspaces/:id/members",
+            routing::get(get_members).post(add_member),
+        )
+        .route(
+            "/workspaces/:id/members/:user_id",
+            routing::patch(update_member).delete(remove_member),
+        )
+        .route(
+            "/workspaces/:id/webhooks",
+            routing::get(get_webhooks).post(create_webhook),
+        )
+        .route(
+            "/workspaces/:id/webhooks/:webhook_id",
+            routing::patch(update_webhook).delete(delete_webhook),
+        )
+        .route(
+            "/workspaces/:id/webhooks/:webhook_id/deliveries",
+            routing::get(get_webhook_deliveries),
+        )
+        .route("/tags", routing::get(get_tags).post(create_tag))
+        .route(
+            "/tags/:id",
+            routing::get(get_tag).put(update_tag).delete(delete_tag),
+        )
+        .with_state(AppState {
+            db_pool,
+            validation_rules: ValidationRules::from_env(),
+            auth_config: AuthConfig::from_env(),
+            attachment_config,
+            task_event_hub,
+        });
 
     axum::serve(listener, router)
         .await
         .expect("Failed to start server.");
 }
 
-#[derive(Serialize)]
-struct TaskRow {
-    id: i32,
-    name: String,
-    priority: Option<i32>,
-}
-
-async fn get_tasks(
-    State(db_pool): State<PgPool>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    return sqlx::query_as!(TaskRow, "SELECT * FROM tasks ORDER BY id")
-        .fetch_all(&db_pool)
-        .await
-        .map(|rows| {
-            (
-                StatusCode::OK,
-                json!({ "success": true, "data": rows }).to_string(),
-            )
-        })
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                json!({ "success": false, "message": e.to_string() }).to_string(),
-            )
-        });
-}
-
-#[derive(Deserialize)]
-struct CreateTaskRequest {
-    name: String,
-    priority: Option<i32>,
-}
-
-#[derive(Serialize)]
-struct CreateTaskRow {
-    id: i32,
-}
-
-async fn create_task(
-    State(db_pool): State<PgPool>,
-    Json(task): Json<CreateTaskRequest>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    return sqlx::query_as!(
-        CreateTaskRow,
-        "INSERT INTO tasks (name, priority) VALUES ($1, $2) RETURNING id",
-        task.name,
-        task.priority
-    )
-    .fetch_one(&db_pool)
-    .await
-    .map(|row| {
-        (
-            StatusCode::OK,
-            json!({ "success": true, "data": row }).to_string(),
-        )
-    })
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            json!({ "success": false, "message": e.to_string() }).to_string(),
-        )
-    });
-}
-
-#[derive(Deserialize)]
-struct UpdateTaskRequest {
-    name: Option<String>,
-    priority: Option<i32>,
-}
+// Refuses to start against a database migrated by a newer binary, since this one would not
+// know how to read the newer schema.
+async fn check_schema_version(db_pool: &PgPool) -> Result<(), String> {
+    let migrations_table: Option<String> =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations')::TEXT")
+            .fetch_one(db_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+    if migrations_table.is_none() {
+        return Ok(());
+    }
 
-async fn update_task(
-    State(db_pool): State<PgPool>,
-    Path(id): Path<i32>,
-    Json(task): Json<UpdateTaskRequest>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    return sqlx::query!(
-        "UPDATE tasks SET name = $2, priority = $3 WHERE id = $1",
-        id,
-        task.name,
-        task.priority
-    )
-    .execute(&db_pool)
-    .await
-    .map(|_| (StatusCode::OK, json!({ "success": true }).to_string()))
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            json!({ "success": false, "message": e.to_string() }).to_string(),
-        )
-    });
-}
-
-async fn delete_task(
-    State(db_pool): State<PgPool>,
-    Path(id): Path<i32>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    return sqlx::query!("DELETE FROM tasks WHERE id = $1", id)
-        .execute(&db_pool)
+    let applied: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations")
+        .fetch_one(db_pool)
         .await
-        .map(|_| (StatusCode::OK, json!({ "success": true }).to_string()))
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                json!({ "success": false, "message": e.to_string() }).to_string(),
-            )
-        });
+        .map_err(|e| e.to_string())?;
+    let known = MIGRATOR.iter().map(|migration| migration.version).max();
+    match applied {
+        Some(applied) if Some(applied) > known => Err(format!(
+            "database schema version {applied} is newer than the latest known version {}",
+            known.unwrap_or_default()
+        )),
+        _ => Ok(()),
+    }
 }
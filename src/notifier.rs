@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+/// A message for one user, addressed by email.
+pub struct Notification {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers notifications to users. Errors are retried by the caller.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: &Notification) -> Result<(), String>;
+}
+
+/// Selects the notifier with `NOTIFIER` (`log`, the default, or `smtp`).
+pub fn from_env() -> Arc<dyn Notifier> {
+    let notifier = std::env::var("NOTIFIER").unwrap_or("log".to_owned());
+    match notifier.as_str() {
+        "log" => Arc::new(LogNotifier),
+        "smtp" => Arc::new(SmtpNotifier::from_env()),
+        _ => panic!("NOTIFIER must be log or smtp."),
+    }
+}
+
+/// Writes notifications to standard output instead of sending them anywhere.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        println!(
+            "Notification to {}: {}\n{}",
+            notification.to, notification.subject, notification.body
+        );
+        Ok(())
+    }
+}
+
+/// Sends notifications as plain-text email through an SMTP server.
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).unwrap_or_else(|_| panic!("{key} must be set."));
+        let host = var("SMTP_HOST");
+        // `none` is meant for local mail catchers, which don't speak TLS.
+        let tls = std::env::var("SMTP_TLS").unwrap_or("starttls".to_owned());
+        let mut builder = match tls.as_str() {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                .expect("SMTP_HOST must be a valid host name."),
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)
+                .expect("SMTP_HOST must be a valid host name."),
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            _ => panic!("SMTP_TLS must be starttls, tls or none."),
+        };
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            builder = builder.port(port.parse().expect("SMTP_PORT must be a port number."));
+        }
+        if let (Ok(username), Ok(password)) = (
+            std::env::var("SMTP_USERNAME"),
+            std::env::var("SMTP_PASSWORD"),
+        ) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Self {
+            transport: builder.build(),
+            from: var("SMTP_FROM")
+                .parse()
+                .expect("SMTP_FROM must be an email address."),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(notification
+                .to
+                .parse()
+                .map_err(|e| format!("invalid recipient: {e}"))?)
+            .subject(&notification.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(notification.body.clone())
+            .map_err(|e| e.to_string())?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    // A mail catcher speaking just enough SMTP for lettre, which records the commands and message
+    // it receives and answers `RCPT` with `rcpt_reply`.
+    async fn mail_catcher(rcpt_reply: &'static str) -> (u16, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(String::new()));
+        let transcript = received.clone();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                transcript.lock().unwrap().push_str(&format!("{line}\n"));
+                let reply = match line.as_str() {
+                    "." if in_data => {
+                        in_data = false;
+                        "250 Queued"
+                    }
+                    _ if in_data => continue,
+                    "DATA" => {
+                        in_data = true;
+                        "354 Go ahead"
+                    }
+                    "QUIT" => "221 Bye",
+                    line if line.starts_with("RCPT") => rcpt_reply,
+                    _ => "250 OK",
+                };
+                writer
+                    .write_all(format!("{reply}\r\n").as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+        (port, received)
+    }
+
+    fn smtp_notifier(port: u16) -> SmtpNotifier {
+        SmtpNotifier {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+                .port(port)
+                .build(),
+            from: "reminders@example.com".parse().unwrap(),
+        }
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            to: "alice@example.com".to_owned(),
+            subject: "Reminder: Shop".to_owned(),
+            body: "This is your reminder for \"Shop\".".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn smtp_notifier_sends_mail() {
+        let (port, received) = mail_catcher("250 OK").await;
+        smtp_notifier(port).notify(&notification()).await.unwrap();
+        let received = received.lock().unwrap().clone();
+        for expected in [
+            "MAIL FROM:<reminders@example.com>",
+            "RCPT TO:<alice@example.com>",
+            "Subject: Reminder: Shop",
+            "Content-Type: text/plain; charset=utf-8",
+            "This is your reminder for \"Shop\".",
+        ] {
+            assert!(received.contains(expected), "{expected} not in\n{received}");
+        }
+    }
+
+    #[tokio::test]
+    async fn smtp_notifier_reports_rejections() {
+        let (port, _) = mail_catcher("550 No such user").await;
+        let error = smtp_notifier(port)
+            .notify(&notification())
+            .await
+            .unwrap_err();
+        assert!(error.contains("No such user"), "{error}");
+    }
+}
@@ -0,0 +1,536 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+const MAX_INTERVAL: u32 = 1000;
+
+// Periods scanned for the next occurrence before giving up, so a rule that rarely or never
+// matches (e.g. the fifth Friday of every twelfth month) can't loop forever.
+const MAX_PERIODS: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Frequency {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Daily => "DAILY",
+            Self::Weekly => "WEEKLY",
+            Self::Monthly => "MONTHLY",
+            Self::Yearly => "YEARLY",
+        }
+    }
+}
+
+/// A `BYDAY` entry: a weekday, optionally limited to its nth (or nth-last, if negative)
+/// occurrence in the month.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ByWeekday {
+    ordinal: Option<i32>,
+    weekday: Weekday,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum End {
+    Never,
+    /// The last occurrence may fall on this instant.
+    Until(DateTime<Utc>),
+    /// The last occurrence may fall on this day in the rule's timezone.
+    UntilDate(NaiveDate),
+    /// Occurrences left, counting the current one.
+    Count(u32),
+}
+
+/// The subset of RFC 5545 recurrence rules supported for tasks: `FREQ`, `INTERVAL`, `BYDAY`
+/// (ordinals only with `MONTHLY`) and either `UNTIL` or `COUNT`.
+#[derive(Clone, PartialEq)]
+pub struct Recurrence {
+    frequency: Frequency,
+    interval: u32,
+    by_weekday: Vec<ByWeekday>,
+    end: End,
+}
+
+impl Recurrence {
+    /// The occurrence after one due at `due_at`, with the rule that continues the series from
+    /// it, or `None` once the series has ended. Occurrences keep `due_at`'s local time in `tz`.
+    pub fn next(&self, due_at: DateTime<Utc>, tz: Tz) -> Option<(DateTime<Utc>, Recurrence)> {
+        let end = match self.end {
+            End::Count(count) if count <= 1 => return None,
+            End::Count(count) => End::Count(count - 1),
+            end => end,
+        };
+        let local = due_at.with_timezone(&tz).naive_local();
+        let next = (0..MAX_PERIODS)
+            .flat_map(|period| self.period_dates(local.date(), period))
+            .filter(|date| *date >= local.date())
+            .map(|date| local_to_utc(date.and_time(local.time()), tz))
+            .find(|at| *at > due_at)?;
+        let ended = match self.end {
+            End::Until(until) => next > until,
+            End::UntilDate(until) => next.with_timezone(&tz).date_naive() > until,
+            End::Never | End::Count(_) => false,
+        };
+        if ended {
+            return None;
+        }
+        Some((
+            next,
+            Self {
+                end,
+                ..self.clone()
+            },
+        ))
+    }
+
+    // Candidate dates, in order, of the `period`th period after the one containing `anchor`.
+    fn period_dates(&self, anchor: NaiveDate, period: u32) -> Vec<NaiveDate> {
+        let steps = period * self.interval;
+        let mut dates: Vec<NaiveDate> = match self.frequency {
+            Frequency::Daily => anchor
+                .checked_add_days(Days::new(steps.into()))
+                .filter(|date| {
+                    self.by_weekday.is_empty()
+                        || self
+                            .by_weekday
+                            .iter()
+                            .any(|by| by.weekday == date.weekday())
+                })
+                .into_iter()
+                .collect(),
+            Frequency::Weekly => {
+                let Some(week_start) = anchor
+                    .week(Weekday::Mon)
+                    .checked_first_day()
+                    .and_then(|date| date.checked_add_days(Days::new(7 * u64::from(steps))))
+                else {
+                    return Vec::new();
+                };
+                let weekdays = if self.by_weekday.is_empty() {
+                    vec![anchor.weekday()]
+                } else {
+                    self.by_weekday.iter().map(|by| by.weekday).collect()
+                };
+                weekdays
+                    .into_iter()
+                    .filter_map(|weekday| {
+                        week_start
+                            .checked_add_days(Days::new(weekday.num_days_from_monday().into()))
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let Some(month_start) = anchor
+                    .with_day(1)
+                    .and_then(|date| date.checked_add_months(Months::new(steps)))
+                else {
+                    return Vec::new();
+                };
+                if self.by_weekday.is_empty() {
+                    month_start.with_day(anchor.day()).into_iter().collect()
+                } else {
+                    self.by_weekday
+                        .iter()
+                        .flat_map(|by| weekdays_in_month(month_start, *by))
+                        .collect()
+                }
+            }
+            Frequency::Yearly => i32::try_from(steps)
+                .ok()
+                .and_then(|steps| {
+                    NaiveDate::from_ymd_opt(anchor.year() + steps, anchor.month(), anchor.day())
+                })
+                .into_iter()
+                .collect(),
+        };
+        dates.sort();
+        dates.dedup();
+        dates
+    }
+}
+
+// The days of `month_start`'s month matching `by`.
+fn weekdays_in_month(month_start: NaiveDate, by: ByWeekday) -> Vec<NaiveDate> {
+    let days: Vec<NaiveDate> = month_start
+        .iter_days()
+        .take_while(|date| date.month() == month_start.month())
+        .filter(|date| date.weekday() == by.weekday)
+        .collect();
+    match by.ordinal {
+        None => days,
+        Some(ordinal) if ordinal > 0 => days
+            .get(ordinal as usize - 1)
+            .copied()
+            .into_iter()
+            .collect(),
+        Some(ordinal) => days
+            .len()
+            .checked_sub(ordinal.unsigned_abs() as usize)
+            .map(|index| days[index])
+            .into_iter()
+            .collect(),
+    }
+}
+
+// A local time skipped by a DST transition is moved forward by the length of the gap.
+fn local_to_utc(local: NaiveDateTime, tz: Tz) -> DateTime<Utc> {
+    (0..=2)
+        .find_map(|hours| {
+            let local = local.checked_add_signed(chrono::Duration::hours(hours))?;
+            tz.from_local_datetime(&local).earliest()
+        })
+        .map_or_else(|| Utc.from_utc_datetime(&local), |at| at.to_utc())
+}
+
+fn parse_weekday(name: &str) -> Option<Weekday> {
+    match name {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn parse_by_weekday(value: &str) -> Result<ByWeekday, String> {
+    let invalid = || format!("invalid BYDAY entry {value}; expected e.g. MO, 1MO or -1FR");
+    let split = value.len().checked_sub(2).ok_or_else(invalid)?;
+    let weekday = value
+        .get(split..)
+        .and_then(parse_weekday)
+        .ok_or_else(invalid)?;
+    let ordinal = match &value[..split] {
+        "" => None,
+        ordinal => {
+            let ordinal: i32 = ordinal.parse().map_err(|_| invalid())?;
+            if ordinal == 0 || ordinal.unsigned_abs() > 5 {
+                return Err(format!(
+                    "BYDAY ordinal in {value} must be between -5 and 5, not 0"
+                ));
+            }
+            Some(ordinal)
+        }
+    };
+    Ok(ByWeekday { ordinal, weekday })
+}
+
+fn parse_until(value: &str) -> Result<End, String> {
+    if let Ok(until) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Ok(End::Until(until.and_utc()));
+    }
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .map(End::UntilDate)
+        .map_err(|_| format!("invalid UNTIL {value}; expected YYYYMMDD or YYYYMMDDTHHMMSSZ"))
+}
+
+fn parse_number(key: &str, value: &str, max: u32) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|number| (1..=max).contains(number))
+        .ok_or_else(|| format!("{key} must be a whole number between 1 and {max}"))
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim().to_ascii_uppercase();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(&rule);
+        let mut frequency = None;
+        let mut interval = None;
+        let mut by_weekday = None;
+        let mut end = None;
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule part {part}; expected KEY=VALUE"))?;
+            let duplicate = match key {
+                "FREQ" => frequency
+                    .replace(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("unsupported FREQ {value}")),
+                    })
+                    .is_some(),
+                "INTERVAL" => interval
+                    .replace(parse_number(key, value, MAX_INTERVAL)?)
+                    .is_some(),
+                "BYDAY" => by_weekday
+                    .replace(
+                        value
+                            .split(',')
+                            .map(parse_by_weekday)
+                            .collect::<Result<Vec<_>, _>>()?,
+                    )
+                    .is_some(),
+                "UNTIL" => end.replace(parse_until(value)?).is_some(),
+                "COUNT" => end
+                    .replace(End::Count(parse_number(key, value, u32::MAX)?))
+                    .is_some(),
+                _ => return Err(format!("unsupported rule part {key}")),
+            };
+            if duplicate {
+                return Err(match key {
+                    "UNTIL" | "COUNT" => "only one of UNTIL and COUNT can be given".to_owned(),
+                    _ => format!("{key} is given more than once"),
+                });
+            }
+        }
+
+        let frequency = frequency.ok_or("FREQ is required")?;
+        let by_weekday = by_weekday.unwrap_or_default();
+        if frequency == Frequency::Yearly && !by_weekday.is_empty() {
+            return Err("BYDAY is not supported with FREQ=YEARLY".to_owned());
+        }
+        if frequency != Frequency::Monthly && by_weekday.iter().any(|by| by.ordinal.is_some()) {
+            return Err("BYDAY ordinals are only supported with FREQ=MONTHLY".to_owned());
+        }
+        Ok(Self {
+            frequency,
+            interval: interval.unwrap_or(1),
+            by_weekday,
+            end: end.unwrap_or(End::Never),
+        })
+    }
+}
+
+/// Formats the rule canonically, which is how it is stored.
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FREQ={}", self.frequency.as_str())?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        for (index, by) in self.by_weekday.iter().enumerate() {
+            f.write_str(if index == 0 { ";BYDAY=" } else { "," })?;
+            if let Some(ordinal) = by.ordinal {
+                write!(f, "{ordinal}")?;
+            }
+            f.write_str(weekday_name(by.weekday))?;
+        }
+        match self.end {
+            End::Never => Ok(()),
+            End::Until(until) => write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ")),
+            End::UntilDate(until) => write!(f, ";UNTIL={}", until.format("%Y%m%d")),
+            End::Count(count) => write!(f, ";COUNT={count}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::SecondsFormat;
+
+    use super::*;
+
+    // Up to `count` occurrences after the one due at `first`, in UTC.
+    fn occurrences(rule: &str, first: &str, tz: Tz, count: usize) -> Vec<String> {
+        let mut rule: Recurrence = rule.parse().unwrap();
+        let mut due_at: DateTime<Utc> = first.parse().unwrap();
+        let mut dates = Vec::new();
+        while dates.len() < count {
+            let Some((next, rest)) = rule.next(due_at, tz) else {
+                break;
+            };
+            dates.push(next.to_rfc3339_opts(SecondsFormat::Secs, true));
+            (due_at, rule) = (next, rest);
+        }
+        dates
+    }
+
+    #[test]
+    fn weekly_by_weekday() {
+        assert_eq!(
+            occurrences(
+                "FREQ=WEEKLY;BYDAY=MO,WE,FR",
+                "2024-01-01T09:00:00Z",
+                Tz::UTC,
+                4
+            ),
+            [
+                "2024-01-03T09:00:00Z",
+                "2024-01-05T09:00:00Z",
+                "2024-01-08T09:00:00Z",
+                "2024-01-10T09:00:00Z",
+            ]
+        );
+        assert_eq!(
+            occurrences(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH",
+                "2024-01-02T09:00:00Z",
+                Tz::UTC,
+                3
+            ),
+            [
+                "2024-01-04T09:00:00Z",
+                "2024-01-16T09:00:00Z",
+                "2024-01-18T09:00:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_ends_at_the_last_representable_date() {
+        let last = NaiveDate::MAX.and_hms_opt(9, 0, 0).unwrap().and_utc();
+        let rule: Recurrence = "FREQ=WEEKLY;BYDAY=MO,WE,FR,SU".parse().unwrap();
+        let days_left = 7 - last.weekday().num_days_from_monday();
+        for days_before in 0..days_left + 14 {
+            let due_at = last - chrono::Duration::days(days_before.into());
+            match rule.next(due_at, Tz::UTC) {
+                Some((next, _)) => assert!(next > due_at && next <= last),
+                None => assert!(days_before < 7, "no occurrence {days_before} days before"),
+            }
+        }
+        let rule: Recurrence = "FREQ=WEEKLY;INTERVAL=52".parse().unwrap();
+        assert!(rule.next(last, Tz::UTC).is_none());
+        assert!(rule.next(last, Tz::America__New_York).is_none());
+    }
+
+    #[test]
+    fn monthly_by_weekday_ordinals() {
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=2TU", "2024-01-09T09:00:00Z", Tz::UTC, 2),
+            ["2024-02-13T09:00:00Z", "2024-03-12T09:00:00Z"]
+        );
+        assert_eq!(
+            occurrences(
+                "FREQ=MONTHLY;BYDAY=-1FR",
+                "2024-01-26T09:00:00Z",
+                Tz::UTC,
+                2
+            ),
+            ["2024-02-23T09:00:00Z", "2024-03-29T09:00:00Z"]
+        );
+        assert_eq!(
+            occurrences(
+                "FREQ=MONTHLY;BYDAY=-2MO",
+                "2024-01-22T09:00:00Z",
+                Tz::UTC,
+                2
+            ),
+            ["2024-02-19T09:00:00Z", "2024-03-18T09:00:00Z"]
+        );
+        // April 2024 has no fifth Friday.
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=5FR", "2024-03-29T09:00:00Z", Tz::UTC, 1),
+            ["2024-05-31T09:00:00Z"]
+        );
+    }
+
+    #[test]
+    fn count_and_until_end_the_series() {
+        assert_eq!(
+            occurrences("FREQ=DAILY;COUNT=3", "2024-01-01T09:00:00Z", Tz::UTC, 5),
+            ["2024-01-02T09:00:00Z", "2024-01-03T09:00:00Z"]
+        );
+        let rule: Recurrence = "FREQ=DAILY;COUNT=3".parse().unwrap();
+        let (_, rest) = rule
+            .next("2024-01-01T09:00:00Z".parse().unwrap(), Tz::UTC)
+            .unwrap();
+        assert_eq!(rest.to_string(), "FREQ=DAILY;COUNT=2");
+
+        assert_eq!(
+            occurrences(
+                "FREQ=DAILY;UNTIL=20240103",
+                "2024-01-01T09:00:00Z",
+                Tz::UTC,
+                5
+            ),
+            ["2024-01-02T09:00:00Z", "2024-01-03T09:00:00Z"]
+        );
+        assert_eq!(
+            occurrences(
+                "FREQ=DAILY;UNTIL=20240103T080000Z",
+                "2024-01-01T09:00:00Z",
+                Tz::UTC,
+                5
+            ),
+            ["2024-01-02T09:00:00Z"]
+        );
+    }
+
+    #[test]
+    fn daylight_saving_time() {
+        let tz = Tz::America__New_York;
+        // 9:00 local, before and after clocks go forward.
+        assert_eq!(
+            occurrences("FREQ=WEEKLY", "2024-03-04T14:00:00Z", tz, 1),
+            ["2024-03-11T13:00:00Z"]
+        );
+        // 2:30 doesn't exist on 10 March, so it moves to 3:30.
+        assert_eq!(
+            occurrences("FREQ=DAILY", "2024-03-09T07:30:00Z", tz, 1),
+            ["2024-03-10T07:30:00Z"]
+        );
+        // 1:30 happens twice on 3 November; the earlier one is used.
+        assert_eq!(
+            occurrences("FREQ=DAILY", "2024-11-02T05:30:00Z", tz, 2),
+            ["2024-11-03T05:30:00Z", "2024-11-04T06:30:00Z"]
+        );
+    }
+
+    #[test]
+    fn days_missing_from_some_periods_are_skipped() {
+        assert_eq!(
+            occurrences("FREQ=YEARLY", "2024-02-29T09:00:00Z", Tz::UTC, 2),
+            ["2028-02-29T09:00:00Z", "2032-02-29T09:00:00Z"]
+        );
+        assert_eq!(
+            occurrences("FREQ=MONTHLY", "2024-01-31T09:00:00Z", Tz::UTC, 3),
+            [
+                "2024-03-31T09:00:00Z",
+                "2024-05-31T09:00:00Z",
+                "2024-07-31T09:00:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_and_formats_canonically() {
+        let rule: Recurrence = "rrule:freq=monthly;byday=-1fr;count=3".parse().unwrap();
+        assert_eq!(rule.to_string(), "FREQ=MONTHLY;BYDAY=-1FR;COUNT=3");
+        let rule: Recurrence = "FREQ=WEEKLY;INTERVAL=1;UNTIL=20240301".parse().unwrap();
+        assert_eq!(rule.to_string(), "FREQ=WEEKLY;UNTIL=20240301");
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rule in [
+            "FREQ=MONTHLY;BYDAY=-2147483648MO",
+            "FREQ=MONTHLY;BYDAY=6MO",
+            "FREQ=MONTHLY;BYDAY=0MO",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=YEARLY;BYDAY=MO",
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;COUNT=1;UNTIL=20240101",
+            "FREQ=HOURLY",
+            "INTERVAL=2",
+        ] {
+            assert!(rule.parse::<Recurrence>().is_err(), "{rule}");
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, Path};
+use crate::notifier::{Notification, Notifier};
+use crate::tasks::authorize_task;
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::worker::run_batches;
+use crate::workspaces::Role;
+
+const MAX_OFFSET_MINUTES: i32 = 366 * 24 * 60;
+
+// Reminders claimed per round trip, and deliveries tried per reminder before giving up.
+const DELIVERY_BATCH_SIZE: i64 = 100;
+const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+const RETRY_DELAY_SECONDS: f64 = 60.0;
+// Shorter than the retry delay, so a reminder's attempt is recorded before it can be claimed
+// again.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize)]
+pub struct ReminderRow {
+    pub id: i32,
+    pub task_id: i32,
+    pub remind_at: Option<DateTime<Utc>>,
+    pub offset_minutes: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+/// Lists the caller's own reminders for the task.
+pub async fn get_reminders(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(task_id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Viewer).await?;
+    let rows = sqlx::query_as!(
+        ReminderRow,
+        "SELECT id, task_id, remind_at, offset_minutes, created_at, sent_at FROM reminders
+        WHERE task_id = $1 AND user_id = $2
+        ORDER BY id",
+        task_id,
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+/// Exactly one of the fields must be set.
+#[derive(Deserialize)]
+pub struct CreateReminderRequest {
+    remind_at: Option<DateTime<Utc>>,
+    /// Minutes before the task's `due_at`.
+    offset_minutes: Option<i32>,
+}
+
+impl Validate for CreateReminderRequest {
+    fn validate(&mut self, _rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        match (self.remind_at, self.offset_minutes) {
+            (Some(remind_at), None) => {
+                if remind_at <= Utc::now() {
+                    validator.fail("remind_at", "in_past", "must be in the future".to_owned());
+                }
+            }
+            (None, Some(offset_minutes)) => {
+                validator.range("offset_minutes", offset_minutes, &(0..=MAX_OFFSET_MINUTES));
+            }
+            _ => validator.fail(
+                "remind_at",
+                "exactly_one",
+                "exactly one of remind_at and offset_minutes is required".to_owned(),
+            ),
+        }
+        validator.finish()
+    }
+}
+
+// Reminders are personal, so anyone who can see the task can set them.
+pub async fn create_reminder(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(task_id): Path<i32>,
+    Valid(reminder): Valid<CreateReminderRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Viewer).await?;
+    if reminder.offset_minutes.is_some() {
+        let due_at = sqlx::query_scalar!("SELECT due_at FROM tasks WHERE id = $1", task_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if due_at.is_none() {
+            let mut validator = Validator::default();
+            validator.fail(
+                "offset_minutes",
+                "requires_due_at",
+                "requires the task to have a due_at".to_owned(),
+            );
+            validator.finish()?;
+        }
+    }
+    let row = sqlx::query_as!(
+        ReminderRow,
+        "INSERT INTO reminders (task_id, user_id, remind_at, offset_minutes)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, task_id, remind_at, offset_minutes, created_at, sent_at",
+        task_id,
+        user.id,
+        reminder.remind_at,
+        reminder.offset_minutes
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+pub async fn delete_reminder(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((task_id, id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, task_id, Role::Viewer).await?;
+    let result = sqlx::query!(
+        "DELETE FROM reminders WHERE id = $1 AND task_id = $2 AND user_id = $3",
+        id,
+        task_id,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("reminder"));
+    }
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+/// Delivers due reminders through `notifier`, polling every `interval`. Reminders are claimed
+/// with `FOR UPDATE SKIP LOCKED`, so several servers can run this side by side without
+/// claiming a reminder twice.
+pub async fn deliver_reminders(db_pool: PgPool, notifier: Arc<dyn Notifier>, interval: Duration) {
+    run_batches("deliver reminders", interval, DELIVERY_BATCH_SIZE, || {
+        deliver_reminders_batch(&db_pool, notifier.as_ref())
+    })
+    .await;
+}
+
+// Claiming counts as an attempt, and is committed before anything is sent, so no transaction
+// stays open while the notifier waits on a mail server. The retry delay keeps other servers off
+// the claimed reminders until their results are recorded. A server that stops in between leaves
+// them to be retried after the delay.
+async fn deliver_reminders_batch(
+    db_pool: &PgPool,
+    notifier: &dyn Notifier,
+) -> Result<usize, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    // The worker acts on behalf of every user, so it reads tasks past row-level security.
+    sqlx::query_scalar!("SELECT set_config('app.bypass_rls', 'on', TRUE)")
+        .fetch_one(&mut *tx)
+        .await?;
+    // Reminders of completed tasks stay pending, and fire if the task is reopened in time.
+    let reminders = sqlx::query!(
+        r#"SELECT reminders.id, users.email, tasks.name AS task_name, tasks.due_at
+        FROM reminders
+        JOIN tasks ON tasks.id = reminders.task_id
+        JOIN users ON users.id = reminders.user_id
+        WHERE reminders.sent_at IS NULL
+            AND reminders.attempts < $1
+            AND (
+                reminders.last_attempt_at IS NULL
+                OR reminders.last_attempt_at <= now() - make_interval(secs => $3)
+            )
+            AND NOT tasks.completed
+            -- Users removed from the task's workspace are no longer reminded of it.
+            AND EXISTS (
+                SELECT 1 FROM workspace_members
+                WHERE workspace_id = tasks.workspace_id AND user_id = reminders.user_id
+            )
+            AND COALESCE(
+                reminders.remind_at,
+                tasks.due_at - make_interval(mins => reminders.offset_minutes)
+            ) <= now()
+        ORDER BY reminders.id
+        LIMIT $2
+        FOR UPDATE OF reminders SKIP LOCKED"#,
+        MAX_DELIVERY_ATTEMPTS,
+        DELIVERY_BATCH_SIZE,
+        RETRY_DELAY_SECONDS
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let ids: Vec<i32> = reminders.iter().map(|reminder| reminder.id).collect();
+    sqlx::query!(
+        "UPDATE reminders SET attempts = attempts + 1, last_attempt_at = now()
+        WHERE id = ANY($1)",
+        &ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let errors = join_all(reminders.iter().map(|reminder| async {
+        let due = match reminder.due_at {
+            Some(due_at) => format!("It is due at {}.", due_at.format("%Y-%m-%d %H:%M UTC")),
+            None => "It has no due date.".to_owned(),
+        };
+        let notification = Notification {
+            to: reminder.email.clone(),
+            subject: format!("Reminder: {}", reminder.task_name),
+            body: format!(
+                "This is your reminder for \"{}\". {due}",
+                reminder.task_name
+            ),
+        };
+        match tokio::time::timeout(SEND_TIMEOUT, notifier.notify(&notification)).await {
+            Ok(result) => result.err(),
+            Err(_) => Some("timed out".to_owned()),
+        }
+    }))
+    .await;
+
+    let mut tx = db_pool.begin().await?;
+    sqlx::query_scalar!("SELECT set_config('app.bypass_rls', 'on', TRUE)")
+        .fetch_one(&mut *tx)
+        .await?;
+    for (reminder, error) in reminders.iter().zip(errors) {
+        if let Some(error) = &error {
+            eprintln!("Failed to deliver reminder {}: {error}", reminder.id);
+        }
+        sqlx::query!(
+            "UPDATE reminders SET
+                sent_at = CASE WHEN $2::TEXT IS NULL THEN now() END,
+                last_error = $2
+            WHERE id = $1",
+            reminder.id,
+            error
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(reminders.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::async_trait;
+
+    use super::*;
+    use crate::test_util::{insert_task, insert_team, insert_user, personal_workspace};
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent_to: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, notification: &Notification) -> Result<(), String> {
+            self.sent_to.lock().unwrap().push(notification.to.clone());
+            Ok(())
+        }
+    }
+
+    #[sqlx::test]
+    async fn removed_members_are_not_reminded(db_pool: PgPool) {
+        let user_ids = [
+            insert_user(&db_pool, "owner@example.com").await,
+            insert_user(&db_pool, "former@example.com").await,
+        ];
+        let workspace_id = insert_team(&db_pool, user_ids[0], &[]).await;
+        let task_id = insert_task(&db_pool, workspace_id, "Shop").await;
+        // The second user was a member when the reminder was set, and has since been removed.
+        for &user_id in &user_ids {
+            sqlx::query!(
+                "INSERT INTO reminders (task_id, user_id, remind_at) VALUES ($1, $2, now())",
+                task_id,
+                user_id
+            )
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        }
+
+        let notifier = RecordingNotifier::default();
+        let claimed = deliver_reminders_batch(&db_pool, &notifier).await.unwrap();
+        assert_eq!(claimed, 1);
+        assert_eq!(*notifier.sent_to.lock().unwrap(), ["owner@example.com"]);
+    }
+
+    // Checks, while sending, that the reminder is neither locked nor claimed again.
+    struct ConcurrentNotifier {
+        db_pool: PgPool,
+        claimed_again: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl Notifier for ConcurrentNotifier {
+        async fn notify(&self, _notification: &Notification) -> Result<(), String> {
+            sqlx::query!("SELECT id FROM reminders FOR UPDATE NOWAIT")
+                .fetch_all(&self.db_pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            let claimed = deliver_reminders_batch(&self.db_pool, &RecordingNotifier::default())
+                .await
+                .map_err(|e| e.to_string())?;
+            self.claimed_again.lock().unwrap().push(claimed);
+            Ok(())
+        }
+    }
+
+    #[sqlx::test]
+    async fn reminders_are_sent_after_they_are_claimed(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, user_id).await;
+        let task_id = insert_task(&db_pool, workspace_id, "Shop").await;
+        sqlx::query!(
+            "INSERT INTO reminders (task_id, user_id, remind_at) VALUES ($1, $2, now())",
+            task_id,
+            user_id
+        )
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+        let notifier = ConcurrentNotifier {
+            db_pool: db_pool.clone(),
+            claimed_again: Mutex::default(),
+        };
+        let claimed = deliver_reminders_batch(&db_pool, &notifier).await.unwrap();
+        assert_eq!(claimed, 1);
+        assert_eq!(*notifier.claimed_again.lock().unwrap(), [0]);
+        let reminder = sqlx::query!("SELECT attempts, sent_at, last_error FROM reminders")
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+        assert_eq!(reminder.last_error, None);
+        assert!(reminder.sent_at.is_some());
+        assert_eq!(reminder.attempts, 1);
+    }
+}
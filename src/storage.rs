@@ -0,0 +1,320 @@
+use std::{io, path::PathBuf, pin::Pin, sync::Arc};
+
+use aws_sdk_s3::{
+    config::{BehaviorVersion, Credentials, Region},
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use axum::{async_trait, body::Bytes};
+use futures::{Stream, TryStreamExt};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// The contents of a file, read in chunks.
+pub type ByteChunks<'a> = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send + 'a>>;
+
+/// The contents of a stored file.
+pub type FileStream = ByteChunks<'static>;
+
+/// Where attachment files are kept. Keys are generated by the server and may contain `/`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores the file as it is read. If reading it fails, nothing is left behind the key and the
+    /// stream's error is returned.
+    async fn put(&self, key: &str, content_type: &str, data: ByteChunks<'_>) -> io::Result<()>;
+    async fn get(&self, key: &str) -> io::Result<FileStream>;
+    /// Deleting a key that does not exist succeeds.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// Selects the backend with `STORAGE_BACKEND` (`local`, the default, or `s3`).
+pub fn from_env() -> Arc<dyn Storage> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or("local".to_owned());
+    match backend.as_str() {
+        "local" => Arc::new(LocalStorage {
+            root: std::env::var("STORAGE_DIR")
+                .unwrap_or("attachments".to_owned())
+                .into(),
+        }),
+        "s3" => Arc::new(S3Storage::from_env()),
+        _ => panic!("STORAGE_BACKEND must be local or s3."),
+    }
+}
+
+/// Stores files under a directory on the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, _content_type: &str, data: ByteChunks<'_>) -> io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Written aside and renamed so a failed upload never leaves a partial file behind the key.
+        let partial = path.with_extension("partial");
+        let written = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            tokio::io::copy(&mut StreamReader::new(data), &mut file).await?;
+            file.sync_all().await
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&partial, &path).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<FileStream> {
+        let file = tokio::fs::File::open(self.root.join(key)).await?;
+        Ok(Box::pin(ReaderStream::new(file)))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+// S3 requires every part of a multipart upload but the last to be at least 5 MiB.
+const S3_PART_SIZE: usize = 8 * 1024 * 1024;
+
+// Reads about `S3_PART_SIZE` bytes, or the rest of the file if it is shorter.
+async fn read_part(data: &mut ByteChunks<'_>) -> io::Result<Vec<u8>> {
+    let mut part = Vec::new();
+    while part.len() < S3_PART_SIZE {
+        match data.try_next().await? {
+            Some(chunk) => part.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    Ok(part)
+}
+
+/// Stores files in a bucket of S3 or an S3-compatible service such as MinIO.
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).unwrap_or_else(|_| panic!("{key} must be set."));
+        let mut config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(
+                std::env::var("S3_REGION").unwrap_or("us-east-1".to_owned()),
+            ))
+            .credentials_provider(Credentials::new(
+                var("S3_ACCESS_KEY_ID"),
+                var("S3_SECRET_ACCESS_KEY"),
+                None,
+                None,
+                "environment",
+            ));
+        // A custom endpoint means a self-hosted service, which rarely supports bucket subdomains.
+        if let Ok(endpoint) = std::env::var("S3_ENDPOINT") {
+            config = config.endpoint_url(endpoint).force_path_style(true);
+        }
+        Self {
+            client: Client::from_conf(config.build()),
+            bucket: var("S3_BUCKET"),
+        }
+    }
+
+    // Uploads `part` and the rest of `data` as parts of the upload, then completes it.
+    async fn put_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        mut part: Vec<u8>,
+        data: &mut ByteChunks<'_>,
+    ) -> io::Result<()> {
+        let mut parts = Vec::new();
+        while !part.is_empty() {
+            let part_number = parts.len() as i32 + 1;
+            let uploaded = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(uploaded.e_tag)
+                    .build(),
+            );
+            part = read_part(data).await?;
+        }
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    // Files that fit in one part are sent in one request. Larger ones are sent a part at a time,
+    // so only one part is held in memory.
+    async fn put(&self, key: &str, content_type: &str, mut data: ByteChunks<'_>) -> io::Result<()> {
+        let part = read_part(&mut data).await?;
+        if part.len() < S3_PART_SIZE {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .content_type(content_type)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+            return Ok(());
+        }
+
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        let upload_id = upload
+            .upload_id
+            .ok_or_else(|| io::Error::other("S3 returned no upload id"))?;
+        let result = self.put_parts(key, &upload_id, part, &mut data).await;
+        if result.is_err() {
+            // Otherwise the uploaded parts are kept, and billed, until the bucket's lifecycle
+            // rules remove them.
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+        }
+        result
+    }
+
+    async fn get(&self, key: &str) -> io::Result<FileStream> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(service_error) if service_error.is_no_such_key() => {
+                    io::Error::new(io::ErrorKind::NotFound, e)
+                }
+                _ => io::Error::other(e),
+            })?;
+        Ok(Box::pin(ReaderStream::new(object.body.into_async_read())))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    fn chunks(chunks: Vec<io::Result<Bytes>>) -> ByteChunks<'static> {
+        Box::pin(stream::iter(chunks))
+    }
+
+    async fn read(storage: &dyn Storage, key: &str) -> io::Result<Vec<u8>> {
+        let chunks: Vec<Bytes> = storage.get(key).await?.try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    async fn check_storage(storage: &dyn Storage) {
+        let key = format!("tasks/1/{}", crate::auth::generate_token());
+        let data = chunks(vec![
+            Ok(Bytes::from_static(b"Remember ")),
+            Ok(Bytes::from_static(b"the milk")),
+        ]);
+        storage.put(&key, "text/plain", data).await.unwrap();
+        assert_eq!(read(storage, &key).await.unwrap(), b"Remember the milk");
+
+        storage.delete(&key).await.unwrap();
+        let missing = read(storage, &key).await.err().map(|e| e.kind());
+        assert_eq!(missing, Some(io::ErrorKind::NotFound));
+        storage.delete(&key).await.unwrap();
+
+        // Larger than a part, so S3 takes it in several.
+        let large = Bytes::from(vec![7; S3_PART_SIZE + 1]);
+        let data = chunks(vec![Ok(large.slice(..1024)), Ok(large.slice(1024..))]);
+        storage
+            .put(&key, "application/octet-stream", data)
+            .await
+            .unwrap();
+        assert_eq!(read(storage, &key).await.unwrap(), large);
+        storage.delete(&key).await.unwrap();
+
+        for data in [
+            vec![Err(io::Error::other("disconnected"))],
+            vec![Ok(large.clone()), Err(io::Error::other("disconnected"))],
+        ] {
+            let failed = storage.put(&key, "text/plain", chunks(data)).await;
+            assert_eq!(failed.unwrap_err().to_string(), "disconnected");
+            let missing = read(storage, &key).await.err().map(|e| e.kind());
+            assert_eq!(missing, Some(io::ErrorKind::NotFound));
+        }
+    }
+
+    #[tokio::test]
+    async fn local_storage() {
+        let root = std::env::temp_dir().join(crate::auth::generate_token());
+        check_storage(&LocalStorage { root: root.clone() }).await;
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+
+    // Against MinIO, for example:
+    //   docker run -p 9000:9000 -e MINIO_ROOT_USER=minio -e MINIO_ROOT_PASSWORD=minio123 \
+    //     minio/minio server /data
+    // then create a bucket, and run with S3_ENDPOINT=http://localhost:9000, S3_BUCKET,
+    // S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY set and `--ignored`.
+    #[tokio::test]
+    #[ignore = "needs an S3-compatible service, configured with the S3_* variables"]
+    async fn s3_storage() {
+        check_storage(&S3Storage::from_env()).await;
+    }
+}
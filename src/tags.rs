@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, Path, Query};
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::workspaces::{check_role, personal_workspace_id, require_role, Role};
+
+/// A tag as embedded in tasks.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct TagRow {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct WorkspaceTagRow {
+    pub id: i32,
+    pub workspace_id: i32,
+    pub name: String,
+}
+
+/// Checks that the user has at least `required` in the tag's workspace. Tags outside the
+/// user's workspaces are reported as not found.
+async fn authorize_tag(
+    conn: &mut PgConnection,
+    user_id: i32,
+    id: i32,
+    required: Role,
+) -> Result<(), AppError> {
+    let role = sqlx::query_scalar!(
+        "SELECT workspace_members.role
+        FROM tags JOIN workspace_members ON workspace_members.workspace_id = tags.workspace_id
+        WHERE tags.id = $1 AND workspace_members.user_id = $2",
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("tag"))?;
+    check_role(Some(&role), required, "tag")?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct GetTagsQuery {
+    workspace_id: Option<i32>,
+}
+
+/// Lists the tags of every workspace the caller belongs to, or of just one.
+pub async fn get_tags(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Query(query): Query<GetTagsQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let rows = sqlx::query_as!(
+        WorkspaceTagRow,
+        r#"SELECT id, workspace_id AS "workspace_id!", name FROM tags
+        WHERE workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = $1)
+            AND ($2::INTEGER IS NULL OR workspace_id = $2)
+        ORDER BY lower(name), id"#,
+        user.id,
+        query.workspace_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+pub async fn get_tag(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_tag(&mut tx, user.id, id, Role::Viewer).await?;
+    let row = sqlx::query_as!(
+        WorkspaceTagRow,
+        r#"SELECT id, workspace_id AS "workspace_id!", name FROM tags WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("tag"))?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct CreateTagRequest {
+    name: String,
+    /// Defaults to the caller's personal workspace.
+    workspace_id: Option<i32>,
+}
+
+impl Validate for CreateTagRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.tag_name_max_length);
+        validator.finish()
+    }
+}
+
+pub async fn create_tag(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Valid(tag): Valid<CreateTagRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let workspace_id = match tag.workspace_id {
+        Some(workspace_id) => workspace_id,
+        None => personal_workspace_id(&mut tx, user.id).await?,
+    };
+    require_role(&mut tx, user.id, workspace_id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        WorkspaceTagRow,
+        r#"INSERT INTO tags (name, workspace_id) VALUES ($1, $2)
+        RETURNING id, workspace_id AS "workspace_id!", name"#,
+        tag.name,
+        workspace_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateTagRequest {
+    name: String,
+}
+
+impl Validate for UpdateTagRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.tag_name_max_length);
+        validator.finish()
+    }
+}
+
+pub async fn update_tag(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Valid(tag): Valid<UpdateTagRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_tag(&mut tx, user.id, id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        WorkspaceTagRow,
+        r#"UPDATE tags SET name = $2 WHERE id = $1
+        RETURNING id, workspace_id AS "workspace_id!", name"#,
+        id,
+        tag.name
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("tag"))?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Deleting a tag also removes it from every task carrying it.
+pub async fn delete_tag(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_tag(&mut tx, user.id, id, Role::Editor).await?;
+    let result = sqlx::query!("DELETE FROM tags WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("tag"));
+    }
+    tx.commit().await?;
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+/// Makes `names` the complete tag set of a task, creating tags that don't exist yet in the
+/// task's workspace.
+pub async fn set_task_tags(
+    conn: &mut PgConnection,
+    task_id: i32,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM task_tags WHERE task_id = $1", task_id)
+        .execute(&mut *conn)
+        .await?;
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "INSERT INTO tags (name, workspace_id)
+        SELECT unnest($2::TEXT[]), workspace_id FROM tasks WHERE id = $1
+        ON CONFLICT (workspace_id, (lower(name))) DO NOTHING",
+        task_id,
+        names
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO task_tags (task_id, tag_id)
+        SELECT $1, id FROM tags
+        WHERE workspace_id = (SELECT workspace_id FROM tasks WHERE id = $1)
+            AND lower(name) IN (SELECT lower(n) FROM unnest($2::TEXT[]) AS n)",
+        task_id,
+        names
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Loads the tags of the given tasks, keyed by task id.
+pub async fn load_task_tags(
+    conn: &mut PgConnection,
+    task_ids: &[i32],
+) -> Result<HashMap<i32, Vec<TagRow>>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT task_tags.task_id, tags.id, tags.name
+        FROM task_tags JOIN tags ON tags.id = task_tags.tag_id
+        WHERE task_tags.task_id = ANY($1)
+        ORDER BY lower(tags.name), tags.id",
+        task_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut tags: HashMap<i32, Vec<TagRow>> = HashMap::new();
+    for row in rows {
+        tags.entry(row.task_id).or_default().push(TagRow {
+            id: row.id,
+            name: row.name,
+        });
+    }
+    Ok(tags)
+}
@@ -0,0 +1,2394 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, http::StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, FieldError, Path, Query};
+use crate::events::{record_task_event, TaskEvent};
+use crate::lists::authorize_list;
+use crate::recurrence::Recurrence;
+use crate::tags::{load_task_tags, set_task_tags, TagRow};
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::workspaces::{check_role, require_role, Role};
+
+#[derive(Serialize, sqlx::FromRow)]
+struct TaskRow {
+    id: i32,
+    name: String,
+    priority: Option<i32>,
+    completed: bool,
+    completed_at: Option<DateTime<Utc>>,
+    start_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    list_id: i32,
+    parent_id: Option<i32>,
+    owner_id: Option<i32>,
+    workspace_id: Option<i32>,
+    assignee_id: Option<i32>,
+    recurrence: Option<String>,
+    recurrence_tz: Option<String>,
+}
+
+/// A task as returned to clients, with its tags embedded.
+#[derive(Serialize)]
+struct Task {
+    #[serde(flatten)]
+    row: TaskRow,
+    tags: Vec<TagRow>,
+}
+
+async fn with_tags(conn: &mut PgConnection, rows: Vec<TaskRow>) -> Result<Vec<Task>, sqlx::Error> {
+    let task_ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let mut tags = load_task_tags(conn, &task_ids).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Task {
+            tags: tags.remove(&row.id).unwrap_or_default(),
+            row,
+        })
+        .collect())
+}
+
+async fn load_task(conn: &mut PgConnection, id: i32) -> Result<Task, AppError> {
+    let row = sqlx::query_as!(TaskRow, "SELECT * FROM tasks WHERE id = $1", id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::NotFound("task"))?;
+    let task = with_tags(conn, vec![row]).await?.pop();
+    task.ok_or(AppError::NotFound("task"))
+}
+
+// Records an event for each task, carrying the task as clients see it.
+async fn record_task_events(
+    conn: &mut PgConnection,
+    event: TaskEvent,
+    tasks: &[Task],
+) -> Result<(), sqlx::Error> {
+    for task in tasks {
+        // Tasks from before workspaces existed aren't visible to anyone to be told about.
+        if let Some(workspace_id) = task.row.workspace_id {
+            record_task_event(conn, workspace_id, task.row.id, event, json!(task)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Records an event for each of the tasks as they are now, for changes made to them outside the
+/// task handlers. Deletions must be recorded before the tasks are gone.
+pub async fn record_events_for_tasks(
+    conn: &mut PgConnection,
+    event: TaskEvent,
+    ids: &[i32],
+) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query_as!(
+        TaskRow,
+        "SELECT * FROM tasks WHERE id = ANY($1) ORDER BY id",
+        ids
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let tasks = with_tags(conn, rows).await?;
+    record_task_events(conn, event, &tasks).await
+}
+
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 100;
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TaskStatus {
+    #[default]
+    Open,
+    Done,
+    All,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+impl TaskStatus {
+    /// The `completed` value to filter on, if any.
+    fn completed(self) -> Option<bool> {
+        match self {
+            Self::Open => Some(false),
+            Self::Done => Some(true),
+            Self::All => None,
+        }
+    }
+}
+
+/// `?assignee=` takes a user id, `me` or `none`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub enum AssigneeFilter {
+    Me,
+    Unassigned,
+    User(i32),
+}
+
+impl TryFrom<String> for AssigneeFilter {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "me" => Ok(Self::Me),
+            "none" => Ok(Self::Unassigned),
+            id => id
+                .parse()
+                .map(Self::User)
+                .map_err(|_| format!("invalid assignee {value}; expected a user id, me or none")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GetTasksQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    after: Option<String>,
+    workspace_id: Option<i32>,
+    list_id: Option<i32>,
+    parent_id: Option<i32>,
+    assignee: Option<AssigneeFilter>,
+    name: Option<String>,
+    priority_min: Option<i32>,
+    priority_max: Option<i32>,
+    has_priority: Option<bool>,
+    #[serde(default)]
+    status: TaskStatus,
+    #[serde(default)]
+    tag: Vec<String>,
+    #[serde(default)]
+    tag_match: TagMatch,
+    sort: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TaskSortColumn {
+    Id,
+    Name,
+    Priority,
+    DueAt,
+}
+
+impl TaskSortColumn {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(Self::Id),
+            "name" => Some(Self::Name),
+            "priority" => Some(Self::Priority),
+            "due_at" => Some(Self::DueAt),
+            _ => None,
+        }
+    }
+
+    fn as_sql(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Priority => "priority",
+            Self::DueAt => "due_at",
+        }
+    }
+
+    fn is_nullable(self) -> bool {
+        matches!(self, Self::Priority | Self::DueAt)
+    }
+
+    fn value_of(self, row: &TaskRow) -> serde_json::Value {
+        match self {
+            Self::Id => json!(row.id),
+            Self::Name => json!(row.name),
+            Self::Priority => json!(row.priority),
+            Self::DueAt => json!(row.due_at),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TaskSortKey {
+    column: TaskSortColumn,
+    descending: bool,
+}
+
+// Parses `sort=priority,-id` style lists. `id` is always appended as the final key so the
+// order is total, which keyset pagination relies on.
+pub fn parse_sort(sort: Option<&str>) -> Result<Vec<TaskSortKey>, String> {
+    let mut keys: Vec<TaskSortKey> = Vec::new();
+    for part in sort
+        .unwrap_or_default()
+        .split(',')
+        .filter(|part| !part.is_empty())
+    {
+        let (name, descending) = match part.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (part, false),
+        };
+        let column =
+            TaskSortColumn::parse(name).ok_or(format!("cannot sort by unknown field {name}"))?;
+        if keys.iter().any(|key| key.column == column) {
+            return Err(format!("field {name} appears more than once in sort"));
+        }
+        keys.push(TaskSortKey { column, descending });
+    }
+    if !keys.iter().any(|key| key.column == TaskSortColumn::Id) {
+        keys.push(TaskSortKey {
+            column: TaskSortColumn::Id,
+            descending: false,
+        });
+    }
+    Ok(keys)
+}
+
+enum CursorValue {
+    Null,
+    Int(i32),
+    Text(String),
+    Timestamp(DateTime<Utc>),
+}
+
+// Cursors are opaque to clients; they wrap the sort key values of the last row on a page.
+fn encode_cursor(row: &TaskRow, keys: &[TaskSortKey]) -> String {
+    let values: Vec<_> = keys.iter().map(|key| key.column.value_of(row)).collect();
+    URL_SAFE_NO_PAD.encode(serde_json::Value::Array(values).to_string())
+}
+
+fn decode_cursor(cursor: &str, keys: &[TaskSortKey]) -> Option<Vec<CursorValue>> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let values: Vec<serde_json::Value> = serde_json::from_slice(&bytes).ok()?;
+    if values.len() != keys.len() {
+        return None;
+    }
+    keys.iter()
+        .zip(values)
+        .map(|(key, value)| match (key.column, value) {
+            (TaskSortColumn::Priority | TaskSortColumn::DueAt, serde_json::Value::Null) => {
+                Some(CursorValue::Null)
+            }
+            (TaskSortColumn::Id | TaskSortColumn::Priority, serde_json::Value::Number(n)) => {
+                Some(CursorValue::Int(n.as_i64()?.try_into().ok()?))
+            }
+            (TaskSortColumn::Name, serde_json::Value::String(s)) => Some(CursorValue::Text(s)),
+            (TaskSortColumn::DueAt, serde_json::Value::String(s)) => {
+                Some(CursorValue::Timestamp(s.parse().ok()?))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn push_cursor_value(builder: &mut QueryBuilder<'_, Postgres>, value: &CursorValue) {
+    match value {
+        CursorValue::Null => builder.push("NULL"),
+        CursorValue::Int(n) => builder.push_bind(*n),
+        CursorValue::Text(s) => builder.push_bind(s.clone()),
+        CursorValue::Timestamp(t) => builder.push_bind(*t),
+    };
+}
+
+// Rows after the cursor in `ORDER BY k1, k2, ... NULLS LAST` order: for some key, every
+// earlier key is equal and this key is strictly past the cursor value.
+fn push_cursor_condition(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    keys: &[TaskSortKey],
+    values: &[CursorValue],
+) {
+    builder.push(" AND (FALSE");
+    for (i, (key, value)) in keys.iter().zip(values).enumerate() {
+        // Nothing sorts strictly after NULL when NULLs come last.
+        if let CursorValue::Null = value {
+            continue;
+        }
+        builder.push(" OR (TRUE");
+        for (prev_key, prev_value) in keys[..i].iter().zip(values) {
+            builder.push(" AND ").push(prev_key.column.as_sql());
+            if let CursorValue::Null = prev_value {
+                builder.push(" IS NULL");
+            } else {
+                builder.push(" = ");
+                push_cursor_value(builder, prev_value);
+            }
+        }
+        let column = key.column.as_sql();
+        builder
+            .push(" AND (")
+            .push(column)
+            .push(if key.descending { " < " } else { " > " });
+        push_cursor_value(builder, value);
+        if key.column.is_nullable() {
+            builder.push(" OR ").push(column).push(" IS NULL");
+        }
+        builder.push("))");
+    }
+    builder.push(")");
+}
+
+fn escape_like(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn push_task_filters(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    query: &GetTasksQuery,
+    user_id: i32,
+) {
+    if let Some(workspace_id) = query.workspace_id {
+        builder.push(" AND workspace_id = ").push_bind(workspace_id);
+    }
+    if let Some(list_id) = query.list_id {
+        builder.push(" AND list_id = ").push_bind(list_id);
+    }
+    if let Some(parent_id) = query.parent_id {
+        builder.push(" AND parent_id = ").push_bind(parent_id);
+    }
+    match query.assignee {
+        Some(AssigneeFilter::Me) => builder.push(" AND assignee_id = ").push_bind(user_id),
+        Some(AssigneeFilter::User(assignee_id)) => {
+            builder.push(" AND assignee_id = ").push_bind(assignee_id)
+        }
+        Some(AssigneeFilter::Unassigned) => builder.push(" AND assignee_id IS NULL"),
+        None => builder,
+    };
+    if let Some(name) = &query.name {
+        builder
+            .push(" AND name ILIKE '%' || ")
+            .push_bind(escape_like(name))
+            .push(" || '%'");
+    }
+    if let Some(priority_min) = query.priority_min {
+        builder.push(" AND priority >= ").push_bind(priority_min);
+    }
+    if let Some(priority_max) = query.priority_max {
+        builder.push(" AND priority <= ").push_bind(priority_max);
+    }
+    match query.has_priority {
+        Some(true) => builder.push(" AND priority IS NOT NULL"),
+        Some(false) => builder.push(" AND priority IS NULL"),
+        None => builder,
+    };
+    if let Some(completed) = query.status.completed() {
+        builder.push(" AND completed = ").push_bind(completed);
+    }
+    if !query.tag.is_empty() {
+        builder
+            .push(
+                " AND id IN (SELECT task_tags.task_id FROM task_tags
+                JOIN tags ON tags.id = task_tags.tag_id
+                WHERE lower(tags.name) IN (SELECT lower(n) FROM unnest(",
+            )
+            .push_bind(query.tag.clone())
+            .push("::TEXT[]) AS n)");
+        if let TagMatch::All = query.tag_match {
+            builder
+                .push(
+                    " GROUP BY task_tags.task_id
+                    HAVING COUNT(*) = (SELECT COUNT(DISTINCT lower(n)) FROM unnest(",
+                )
+                .push_bind(query.tag.clone())
+                .push("::TEXT[]) AS n)");
+        }
+        builder.push(")");
+    }
+}
+
+pub async fn get_tasks(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Query(query): Query<GetTasksQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let response = fetch_tasks(&mut tx, user.id, query).await?;
+    tx.commit().await?;
+    Ok(response)
+}
+
+/// Tasks assigned to the caller, across all their workspaces.
+pub async fn get_my_tasks(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Query(mut query): Query<GetTasksQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    query.assignee = Some(AssigneeFilter::Me);
+    let mut tx = user.begin(&db_pool).await?;
+    let response = fetch_tasks(&mut tx, user.id, query).await?;
+    tx.commit().await?;
+    Ok(response)
+}
+
+// Lists sort their tasks by the list's `default_sort` unless the request asks otherwise.
+pub async fn get_list_tasks(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(list_id): Path<i32>,
+    Query(mut query): Query<GetTasksQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_list(&mut tx, user.id, list_id, Role::Viewer).await?;
+    let default_sort = sqlx::query_scalar!("SELECT default_sort FROM lists WHERE id = $1", list_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    query.list_id = Some(list_id);
+    query.sort = query.sort.or(Some(default_sort));
+    let response = fetch_tasks(&mut tx, user.id, query).await?;
+    tx.commit().await?;
+    Ok(response)
+}
+
+// Lists the tasks of every workspace the user belongs to, or of `workspace_id` only.
+async fn fetch_tasks(
+    conn: &mut PgConnection,
+    user_id: i32,
+    query: GetTasksQuery,
+) -> Result<(StatusCode, String), AppError> {
+    if let Some(workspace_id) = query.workspace_id {
+        require_role(conn, user_id, workspace_id, Role::Viewer).await?;
+    }
+    if query.offset.is_some() && query.after.is_some() {
+        return Err(AppError::BadRequest(
+            "conflicting_pagination",
+            "offset and after cannot be used together".to_owned(),
+        ));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    let mut validator = Validator::default();
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        validator.fail(
+            "limit",
+            "out_of_range",
+            format!("must be between 1 and {MAX_PAGE_LIMIT}"),
+        );
+    }
+    if offset < 0 {
+        validator.fail("offset", "out_of_range", "must not be negative".to_owned());
+    }
+    if let (Some(priority_min), Some(priority_max)) = (query.priority_min, query.priority_max) {
+        if priority_min > priority_max {
+            validator.fail(
+                "priority_max",
+                "out_of_range",
+                "must not be less than priority_min".to_owned(),
+            );
+        }
+    }
+    validator.finish()?;
+    let sort_keys = parse_sort(query.sort.as_deref())
+        .map_err(|message| AppError::BadRequest("invalid_sort", message))?;
+    let after = match &query.after {
+        Some(cursor) => Some(
+            decode_cursor(cursor, &sort_keys).ok_or(AppError::BadRequest(
+                "invalid_cursor",
+                "invalid cursor".to_owned(),
+            ))?,
+        ),
+        None => None,
+    };
+
+    let mut count_query = QueryBuilder::new(
+        "SELECT COUNT(*) FROM tasks
+        WHERE workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = ",
+    );
+    count_query.push_bind(user_id).push(")");
+    push_task_filters(&mut count_query, &query, user_id);
+    let total: i64 = count_query
+        .build_query_scalar()
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let mut rows_query = QueryBuilder::new(
+        "SELECT * FROM tasks
+        WHERE workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = ",
+    );
+    rows_query.push_bind(user_id).push(")");
+    push_task_filters(&mut rows_query, &query, user_id);
+    if let Some(values) = &after {
+        push_cursor_condition(&mut rows_query, &sort_keys, values);
+    }
+    rows_query.push(" ORDER BY ");
+    let mut order_by = rows_query.separated(", ");
+    for key in &sort_keys {
+        order_by
+            .push(key.column.as_sql())
+            .push_unseparated(if key.descending {
+                " DESC NULLS LAST"
+            } else {
+                " ASC NULLS LAST"
+            });
+    }
+    // Fetch one extra row to find out whether another page follows.
+    rows_query
+        .push(" LIMIT ")
+        .push_bind(limit + 1)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let mut rows: Vec<TaskRow> = rows_query.build_query_as().fetch_all(&mut *conn).await?;
+
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|row| encode_cursor(row, &sort_keys))
+    } else {
+        None
+    };
+    let tasks = with_tags(conn, rows).await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": tasks, "next_cursor": next_cursor, "total": total })
+            .to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct GetTaskQuery {
+    /// Nest the whole subtree instead of only the direct children.
+    #[serde(default)]
+    subtree: bool,
+}
+
+#[derive(Serialize)]
+struct TaskNode {
+    #[serde(flatten)]
+    task: Task,
+    /// Omitted on the deepest loaded level, where children weren't fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<TaskNode>>,
+}
+
+/// Loads the descendants of a task, `max_depth` levels deep or all of them if `None`.
+/// Subtasks always share their parent's workspace, so they're visible to whoever can see it.
+async fn load_descendants(
+    conn: &mut PgConnection,
+    id: i32,
+    max_depth: Option<i32>,
+) -> Result<Vec<TaskRow>, sqlx::Error> {
+    sqlx::query_as(
+        "WITH RECURSIVE subtree AS (
+            SELECT tasks.*, 1 AS depth FROM tasks WHERE parent_id = $1
+            UNION ALL
+            SELECT tasks.*, subtree.depth + 1 FROM tasks JOIN subtree ON tasks.parent_id = subtree.id
+            WHERE $2::INTEGER IS NULL OR subtree.depth < $2
+        )
+        SELECT * FROM subtree ORDER BY id",
+    )
+    .bind(id)
+    .bind(max_depth)
+    .fetch_all(conn)
+    .await
+}
+
+fn take_children(
+    parent_id: i32,
+    by_parent: &mut HashMap<i32, Vec<Task>>,
+    max_depth: Option<i32>,
+) -> Vec<TaskNode> {
+    let children = by_parent.remove(&parent_id).unwrap_or_default();
+    children
+        .into_iter()
+        .map(|task| {
+            let id = task.row.id;
+            let child_depth = max_depth.map(|depth| depth - 1);
+            TaskNode {
+                children: (child_depth != Some(0))
+                    .then(|| take_children(id, by_parent, child_depth)),
+                task,
+            }
+        })
+        .collect()
+}
+
+/// Checks that the user has at least `required` in the task's workspace, returning the
+/// workspace id.
+pub async fn authorize_task(
+    conn: &mut PgConnection,
+    user_id: i32,
+    id: i32,
+    required: Role,
+) -> Result<i32, AppError> {
+    let membership = sqlx::query!(
+        r#"SELECT tasks.workspace_id AS "workspace_id!", workspace_members.role
+        FROM tasks JOIN workspace_members ON workspace_members.workspace_id = tasks.workspace_id
+        WHERE tasks.id = $1 AND workspace_members.user_id = $2"#,
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("task"))?;
+    check_role(Some(&membership.role), required, "task")?;
+    Ok(membership.workspace_id)
+}
+
+pub async fn get_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Query(query): Query<GetTaskQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, id, Role::Viewer).await?;
+    let row = sqlx::query_as!(TaskRow, "SELECT * FROM tasks WHERE id = $1", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::NotFound("task"))?;
+    let max_depth = if query.subtree { None } else { Some(1) };
+    let mut rows = vec![row];
+    rows.extend(load_descendants(&mut tx, id, max_depth).await?);
+
+    let mut tasks = with_tags(&mut tx, rows).await?;
+    tx.commit().await?;
+    let root = tasks.remove(0);
+    let mut by_parent: HashMap<i32, Vec<Task>> = HashMap::new();
+    for task in tasks {
+        if let Some(parent_id) = task.row.parent_id {
+            by_parent.entry(parent_id).or_default().push(task);
+        }
+    }
+    let node = TaskNode {
+        children: Some(take_children(id, &mut by_parent, max_depth)),
+        task: root,
+    };
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": node }).to_string(),
+    ))
+}
+
+// Rejects a list outside the task's workspace.
+async fn check_list(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    list_id: i32,
+) -> Result<(), AppError> {
+    let list_exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM lists WHERE id = $1 AND workspace_id = $2) AS "exists!""#,
+        list_id,
+        workspace_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !list_exists {
+        return Err(AppError::Validation(vec![FieldError {
+            field: "list_id",
+            code: "not_found",
+            message: "must be an existing list in the same workspace".to_owned(),
+        }]));
+    }
+    Ok(())
+}
+
+// Rejects a parent outside the task's workspace, and for an existing task `id`, a parent that
+// is the task itself or one of its descendants, which would make a cycle.
+async fn check_parent(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    id: Option<i32>,
+    parent_id: i32,
+) -> Result<(), AppError> {
+    let parent_exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND workspace_id = $2) AS "exists!""#,
+        parent_id,
+        workspace_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !parent_exists {
+        return Err(AppError::Validation(vec![FieldError {
+            field: "parent_id",
+            code: "not_found",
+            message: "must be an existing task in the same workspace".to_owned(),
+        }]));
+    }
+    let Some(id) = id else {
+        return Ok(());
+    };
+
+    let creates_cycle = sqlx::query_scalar!(
+        r#"WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM tasks WHERE id = $2
+            UNION
+            SELECT tasks.id, tasks.parent_id FROM tasks
+            JOIN ancestors ON tasks.id = ancestors.parent_id
+        )
+        SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $1) AS "exists!""#,
+        id,
+        parent_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if creates_cycle {
+        return Err(AppError::Validation(vec![FieldError {
+            field: "parent_id",
+            code: "cycle",
+            message: "must not be the task itself or one of its subtasks".to_owned(),
+        }]));
+    }
+    Ok(())
+}
+
+// Only members of the task's workspace can be assigned to it.
+async fn check_assignee(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    assignee_id: i32,
+) -> Result<(), AppError> {
+    let is_member = sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM workspace_members WHERE workspace_id = $1 AND user_id = $2
+        ) AS "exists!""#,
+        workspace_id,
+        assignee_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !is_member {
+        return Err(AppError::Validation(vec![FieldError {
+            field: "assignee_id",
+            code: "not_member",
+            message: "must be a member of the task's workspace".to_owned(),
+        }]));
+    }
+    Ok(())
+}
+
+/// Sets a task's assignee, recording an assignment event if it changed.
+pub async fn assign_task(
+    conn: &mut PgConnection,
+    actor_id: i32,
+    id: i32,
+    assignee_id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let previous_assignee_id =
+        sqlx::query_scalar!("SELECT assignee_id FROM tasks WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(&mut *conn)
+            .await?;
+    if previous_assignee_id == assignee_id {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "UPDATE tasks SET assignee_id = $2 WHERE id = $1",
+        id,
+        assignee_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO task_assignment_events (task_id, actor_id, previous_assignee_id, assignee_id)
+        VALUES ($1, $2, $3, $4)",
+        id,
+        actor_id,
+        previous_assignee_id,
+        assignee_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AssignmentEventRow {
+    id: i64,
+    actor_id: Option<i32>,
+    previous_assignee_id: Option<i32>,
+    assignee_id: Option<i32>,
+    created_at: DateTime<Utc>,
+}
+
+/// The task's assignment history, oldest first.
+pub async fn get_task_assignments(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, id, Role::Viewer).await?;
+    let rows = sqlx::query_as!(
+        AssignmentEventRow,
+        "SELECT id, actor_id, previous_assignee_id, assignee_id, created_at
+        FROM task_assignment_events
+        WHERE task_id = $1
+        ORDER BY id",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+const MAX_AGENDA_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+pub struct AgendaQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    tz: Option<String>,
+    #[serde(default)]
+    status: TaskStatus,
+}
+
+#[derive(Deserialize)]
+pub struct OverdueQuery {
+    tz: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AgendaDay {
+    date: NaiveDate,
+    tasks: Vec<Task>,
+}
+
+fn parse_timezone(tz: Option<&str>) -> Result<Tz, AppError> {
+    tz.map_or(Ok(Tz::UTC), |tz| {
+        tz.parse()
+            .map_err(|_| AppError::BadRequest("invalid_timezone", format!("unknown timezone {tz}")))
+    })
+}
+
+// Midnight may not exist on DST transition days, in which case the day starts at the first
+// valid local time after it.
+fn start_of_day(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    (0..24)
+        .find_map(|hour| {
+            tz.from_local_datetime(&(midnight + chrono::Duration::hours(hour)))
+                .earliest()
+        })
+        .map_or_else(|| Utc.from_utc_datetime(&midnight), |start| start.to_utc())
+}
+
+// Groups tasks already ordered by `due_at` into one entry per local calendar day.
+fn group_by_due_day(tasks: Vec<Task>, tz: Tz) -> Vec<AgendaDay> {
+    let mut days: Vec<AgendaDay> = Vec::new();
+    for task in tasks {
+        let Some(due_at) = task.row.due_at else {
+            continue;
+        };
+        let date = due_at.with_timezone(&tz).date_naive();
+        match days.last_mut() {
+            Some(day) if day.date == date => day.tasks.push(task),
+            _ => days.push(AgendaDay {
+                date,
+                tasks: vec![task],
+            }),
+        }
+    }
+    days
+}
+
+pub async fn get_agenda(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Query(query): Query<AgendaQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let tz = parse_timezone(query.tz.as_deref())?;
+    let mut validator = Validator::default();
+    if query.to < query.from {
+        validator.fail("to", "out_of_range", "must not be before from".to_owned());
+    } else if (query.to - query.from).num_days() >= MAX_AGENDA_DAYS {
+        validator.fail(
+            "to",
+            "out_of_range",
+            format!("range must be shorter than {MAX_AGENDA_DAYS} days"),
+        );
+    }
+    validator.finish()?;
+
+    // `to` is inclusive, so the range ends at the start of the following day.
+    let start = start_of_day(query.from, tz);
+    let end = start_of_day(query.to + Days::new(1), tz);
+    let mut tx = user.begin(&db_pool).await?;
+    let rows = sqlx::query_as!(
+        TaskRow,
+        "SELECT * FROM tasks
+        WHERE workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = $4)
+            AND due_at >= $1 AND due_at < $2
+            AND ($3::BOOLEAN IS NULL OR completed = $3)
+        ORDER BY due_at, id",
+        start,
+        end,
+        query.status.completed(),
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let tasks = with_tags(&mut tx, rows).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": group_by_due_day(tasks, tz) }).to_string(),
+    ))
+}
+
+pub async fn get_overdue_tasks(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Query(query): Query<OverdueQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let tz = parse_timezone(query.tz.as_deref())?;
+    let mut tx = user.begin(&db_pool).await?;
+    let rows = sqlx::query_as!(
+        TaskRow,
+        "SELECT * FROM tasks
+        WHERE workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = $1)
+            AND due_at < now() AND NOT completed
+        ORDER BY due_at, id",
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let tasks = with_tags(&mut tx, rows).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": group_by_due_day(tasks, tz) }).to_string(),
+    ))
+}
+
+// Replaces a valid rule with its canonical form, which is how rules are stored.
+fn check_recurrence(validator: &mut Validator, recurrence: &mut Option<String>) {
+    if let Some(rule) = recurrence {
+        match rule.parse::<Recurrence>() {
+            Ok(parsed) => *rule = parsed.to_string(),
+            Err(message) => validator.fail("recurrence", "invalid", message),
+        }
+    }
+}
+
+fn check_recurrence_tz(validator: &mut Validator, tz: Option<&str>) {
+    if let Some(tz) = tz {
+        if tz.parse::<Tz>().is_err() {
+            validator.fail(
+                "recurrence_tz",
+                "invalid_timezone",
+                format!("unknown timezone {tz}"),
+            );
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateTaskRequest {
+    name: String,
+    priority: Option<i32>,
+    start_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Defaults to the workspace's default list.
+    list_id: Option<i32>,
+    parent_id: Option<i32>,
+    /// Defaults to the list's workspace, the parent's workspace, or the caller's personal
+    /// workspace.
+    workspace_id: Option<i32>,
+    assignee_id: Option<i32>,
+    recurrence: Option<String>,
+    recurrence_tz: Option<String>,
+}
+
+impl Validate for CreateTaskRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.name_max_length);
+        if let Some(priority) = self.priority {
+            validator.range("priority", priority, &rules.priority_range);
+        }
+        validator.dates("start_at", self.start_at, self.due_at);
+        validator.distinct_texts("tags", &mut self.tags, rules.tag_name_max_length);
+        check_recurrence(&mut validator, &mut self.recurrence);
+        if self.recurrence.is_some() && self.due_at.is_none() {
+            validator.fail(
+                "recurrence",
+                "requires_due_at",
+                "requires due_at".to_owned(),
+            );
+        }
+        check_recurrence_tz(&mut validator, self.recurrence_tz.as_deref());
+        validator.finish()
+    }
+}
+
+#[derive(Serialize)]
+struct CreateTaskRow {
+    id: i32,
+}
+
+pub async fn create_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Valid(task): Valid<CreateTaskRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let response = insert_task(&mut tx, &user, task).await?;
+    tx.commit().await?;
+    Ok(response)
+}
+
+pub async fn create_list_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(list_id): Path<i32>,
+    Valid(mut task): Valid<CreateTaskRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let workspace_id = authorize_list(&mut tx, user.id, list_id, Role::Editor).await?;
+    task.list_id = Some(list_id);
+    task.workspace_id = task.workspace_id.or(Some(workspace_id));
+    let response = insert_task(&mut tx, &user, task).await?;
+    tx.commit().await?;
+    Ok(response)
+}
+
+async fn insert_task(
+    conn: &mut PgConnection,
+    user: &AuthUser,
+    task: CreateTaskRequest,
+) -> Result<(StatusCode, String), AppError> {
+    let workspace_id = match task.workspace_id {
+        Some(workspace_id) => workspace_id,
+        None => {
+            sqlx::query_scalar!(
+                r#"SELECT COALESCE(
+                    (SELECT workspace_id FROM lists WHERE id = $2 AND workspace_id IN (
+                        SELECT workspace_id FROM workspace_members WHERE user_id = $1
+                    )),
+                    (SELECT workspace_id FROM tasks WHERE id = $3 AND workspace_id IN (
+                        SELECT workspace_id FROM workspace_members WHERE user_id = $1
+                    )),
+                    (SELECT id FROM workspaces WHERE personal_for = $1)
+                ) AS "id!""#,
+                user.id,
+                task.list_id,
+                task.parent_id
+            )
+            .fetch_one(&mut *conn)
+            .await?
+        }
+    };
+    require_role(conn, user.id, workspace_id, Role::Editor).await?;
+    if let Some(list_id) = task.list_id {
+        check_list(conn, workspace_id, list_id).await?;
+    }
+    if let Some(parent_id) = task.parent_id {
+        check_parent(conn, workspace_id, None, parent_id).await?;
+    }
+    if let Some(assignee_id) = task.assignee_id {
+        check_assignee(conn, workspace_id, assignee_id).await?;
+    }
+    let row = sqlx::query_as!(
+        CreateTaskRow,
+        "INSERT INTO tasks (
+            name, priority, start_at, due_at, list_id, parent_id, owner_id, workspace_id,
+            recurrence, recurrence_tz
+        )
+        VALUES ($1, $2, $3, $4,
+            COALESCE($5, (SELECT id FROM lists WHERE workspace_id = $8 AND is_default)), $6, $7, $8,
+            $9, $10)
+        RETURNING id",
+        task.name,
+        task.priority,
+        task.start_at,
+        task.due_at,
+        task.list_id,
+        task.parent_id,
+        user.id,
+        workspace_id,
+        task.recurrence,
+        task.recurrence_tz
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    set_task_tags(conn, row.id, &task.tags).await?;
+    if task.assignee_id.is_some() {
+        assign_task(conn, user.id, row.id, task.assignee_id).await?;
+    }
+    let created = load_task(conn, row.id).await?;
+    record_task_events(conn, TaskEvent::Created, &[created]).await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// PUT replaces the whole task, so every field must be present. Nullable fields still have
+// to be spelled out as `null`.
+#[derive(Deserialize)]
+pub struct ReplaceTaskRequest {
+    name: String,
+    #[serde(deserialize_with = "Option::deserialize")]
+    priority: Option<i32>,
+    #[serde(deserialize_with = "Option::deserialize")]
+    start_at: Option<DateTime<Utc>>,
+    #[serde(deserialize_with = "Option::deserialize")]
+    due_at: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+    list_id: i32,
+    #[serde(deserialize_with = "Option::deserialize")]
+    parent_id: Option<i32>,
+    #[serde(deserialize_with = "Option::deserialize")]
+    assignee_id: Option<i32>,
+    #[serde(deserialize_with = "Option::deserialize")]
+    recurrence: Option<String>,
+    #[serde(deserialize_with = "Option::deserialize")]
+    recurrence_tz: Option<String>,
+}
+
+impl Validate for ReplaceTaskRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.name_max_length);
+        if let Some(priority) = self.priority {
+            validator.range("priority", priority, &rules.priority_range);
+        }
+        validator.dates("start_at", self.start_at, self.due_at);
+        validator.distinct_texts("tags", &mut self.tags, rules.tag_name_max_length);
+        check_recurrence(&mut validator, &mut self.recurrence);
+        if self.recurrence.is_some() && self.due_at.is_none() {
+            validator.fail(
+                "recurrence",
+                "requires_due_at",
+                "requires due_at".to_owned(),
+            );
+        }
+        check_recurrence_tz(&mut validator, self.recurrence_tz.as_deref());
+        validator.finish()
+    }
+}
+
+pub async fn replace_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Valid(task): Valid<ReplaceTaskRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let workspace_id = authorize_task(&mut tx, user.id, id, Role::Editor).await?;
+    check_list(&mut tx, workspace_id, task.list_id).await?;
+    if let Some(parent_id) = task.parent_id {
+        check_parent(&mut tx, workspace_id, Some(id), parent_id).await?;
+    }
+    if let Some(assignee_id) = task.assignee_id {
+        check_assignee(&mut tx, workspace_id, assignee_id).await?;
+    }
+    let result = sqlx::query!(
+        "UPDATE tasks SET
+            name = $2, priority = $3, start_at = $4, due_at = $5, list_id = $6, parent_id = $7,
+            recurrence = $8, recurrence_tz = $9
+        WHERE id = $1",
+        id,
+        task.name,
+        task.priority,
+        task.start_at,
+        task.due_at,
+        task.list_id,
+        task.parent_id,
+        task.recurrence,
+        task.recurrence_tz
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("task"));
+    }
+    set_task_tags(&mut tx, id, &task.tags).await?;
+    assign_task(&mut tx, user.id, id, task.assignee_id).await?;
+    let updated = load_task(&mut tx, id).await?;
+    record_task_events(&mut tx, TaskEvent::Updated, &[updated]).await?;
+    tx.commit().await?;
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+// Wraps any present value in `Some`, so that together with `#[serde(default)]` a missing field
+// becomes `None` while an explicit `null` becomes `Some(None)`.
+pub fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// PATCH leaves omitted fields untouched. `name`, `tags` and `list_id` cannot be null; the
+// other fields are cleared by an explicit `null`.
+#[derive(Deserialize)]
+pub struct UpdateTaskRequest {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    priority: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    start_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    due_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    tags: Option<Vec<String>>,
+    /// Moves the task to another list.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    list_id: Option<i32>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    parent_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    assignee_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    recurrence: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    recurrence_tz: Option<Option<String>>,
+}
+
+impl Validate for UpdateTaskRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        if let Some(name) = &mut self.name {
+            validator.text("name", name, rules.name_max_length);
+        }
+        if let Some(Some(priority)) = self.priority {
+            validator.range("priority", priority, &rules.priority_range);
+        }
+        if let Some(tags) = &mut self.tags {
+            validator.distinct_texts("tags", tags, rules.tag_name_max_length);
+        }
+        if let Some(recurrence) = &mut self.recurrence {
+            check_recurrence(&mut validator, recurrence);
+        }
+        if let Some(Some(recurrence_tz)) = &self.recurrence_tz {
+            check_recurrence_tz(&mut validator, Some(recurrence_tz));
+        }
+        validator.finish()
+    }
+}
+
+// Checks the dates and recurrence rule the task will have once patched, so that fields left
+// out of the patch are taken into account. The row stays locked until the update.
+async fn check_patched_dates(
+    conn: &mut PgConnection,
+    id: i32,
+    task: &UpdateTaskRequest,
+) -> Result<(), AppError> {
+    let current = sqlx::query!(
+        "SELECT start_at, due_at, recurrence FROM tasks WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("task"))?;
+    let start_at = task.start_at.unwrap_or(current.start_at);
+    let due_at = task.due_at.unwrap_or(current.due_at);
+    let has_recurrence = match &task.recurrence {
+        Some(recurrence) => recurrence.is_some(),
+        None => current.recurrence.is_some(),
+    };
+
+    let mut validator = Validator::default();
+    validator.dates("start_at", start_at, due_at);
+    if has_recurrence && due_at.is_none() {
+        validator.fail(
+            "recurrence",
+            "requires_due_at",
+            "requires due_at".to_owned(),
+        );
+    }
+    validator.finish()
+}
+
+pub async fn update_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Valid(task): Valid<UpdateTaskRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let workspace_id = authorize_task(&mut tx, user.id, id, Role::Editor).await?;
+    check_patched_dates(&mut tx, id, &task).await?;
+    if let Some(list_id) = task.list_id {
+        check_list(&mut tx, workspace_id, list_id).await?;
+    }
+    if let Some(Some(parent_id)) = task.parent_id {
+        check_parent(&mut tx, workspace_id, Some(id), parent_id).await?;
+    }
+    if let Some(Some(assignee_id)) = task.assignee_id {
+        check_assignee(&mut tx, workspace_id, assignee_id).await?;
+    }
+    let result = sqlx::query!(
+        "UPDATE tasks SET
+            name = COALESCE($2, name),
+            priority = CASE WHEN $3 THEN $4 ELSE priority END,
+            start_at = CASE WHEN $5 THEN $6 ELSE start_at END,
+            due_at = CASE WHEN $7 THEN $8 ELSE due_at END,
+            list_id = COALESCE($9, list_id),
+            parent_id = CASE WHEN $10 THEN $11 ELSE parent_id END,
+            recurrence = CASE WHEN $12 THEN $13 ELSE recurrence END,
+            recurrence_tz = CASE WHEN $14 THEN $15 ELSE recurrence_tz END
+        WHERE id = $1",
+        id,
+        task.name,
+        task.priority.is_some(),
+        task.priority.flatten(),
+        task.start_at.is_some(),
+        task.start_at.flatten(),
+        task.due_at.is_some(),
+        task.due_at.flatten(),
+        task.list_id,
+        task.parent_id.is_some(),
+        task.parent_id.flatten(),
+        task.recurrence.is_some(),
+        task.recurrence.clone().flatten(),
+        task.recurrence_tz.is_some(),
+        task.recurrence_tz.clone().flatten()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("task"));
+    }
+    if let Some(tags) = &task.tags {
+        set_task_tags(&mut tx, id, tags).await?;
+    }
+    if let Some(assignee_id) = task.assignee_id {
+        assign_task(&mut tx, user.id, id, assignee_id).await?;
+    }
+    let updated = load_task(&mut tx, id).await?;
+    record_task_events(&mut tx, TaskEvent::Updated, &[updated]).await?;
+    tx.commit().await?;
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct CascadeQuery {
+    /// Apply the operation to the task's whole subtree.
+    #[serde(default)]
+    cascade: bool,
+}
+
+// The rule a recurring task was stored with, and the timezone to evaluate it in.
+fn task_recurrence(row: &TaskRow) -> Result<Option<(Recurrence, Tz)>, AppError> {
+    let Some(rule) = &row.recurrence else {
+        return Ok(None);
+    };
+    // Both were validated when they were stored.
+    let recurrence = rule.parse().map_err(AppError::Internal)?;
+    let tz = parse_timezone(row.recurrence_tz.as_deref())?;
+    Ok(Some((recurrence, tz)))
+}
+
+// Creates the next occurrence of a recurring task, with the same fields, tags and relative
+// reminders, shifted to the next due date. Returns `None` once the series has ended.
+async fn create_next_occurrence(
+    conn: &mut PgConnection,
+    actor_id: i32,
+    row: &TaskRow,
+) -> Result<Option<TaskRow>, AppError> {
+    let (Some((recurrence, tz)), Some(due_at)) = (task_recurrence(row)?, row.due_at) else {
+        return Ok(None);
+    };
+    let Some((next_due_at, next_recurrence)) = recurrence.next(due_at, tz) else {
+        return Ok(None);
+    };
+    let next = sqlx::query_as!(
+        TaskRow,
+        "INSERT INTO tasks (
+            name, priority, start_at, due_at, list_id, parent_id, owner_id, workspace_id,
+            recurrence, recurrence_tz
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING *",
+        row.name,
+        row.priority,
+        row.start_at
+            .map(|start_at| next_due_at - (due_at - start_at)),
+        next_due_at,
+        row.list_id,
+        row.parent_id,
+        row.owner_id,
+        row.workspace_id,
+        next_recurrence.to_string(),
+        row.recurrence_tz
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO task_tags (task_id, tag_id) SELECT $2, tag_id FROM task_tags WHERE task_id = $1",
+        row.id,
+        next.id
+    )
+    .execute(&mut *conn)
+    .await?;
+    // Reminders relative to the due date carry over; absolute ones belong to this occurrence.
+    sqlx::query!(
+        "INSERT INTO reminders (task_id, user_id, offset_minutes)
+        SELECT $2, user_id, offset_minutes FROM reminders
+        WHERE task_id = $1 AND offset_minutes IS NOT NULL",
+        row.id,
+        next.id
+    )
+    .execute(&mut *conn)
+    .await?;
+    if row.assignee_id.is_some() {
+        assign_task(conn, actor_id, next.id, row.assignee_id).await?;
+    }
+    Ok(Some(TaskRow {
+        assignee_id: row.assignee_id,
+        ..next
+    }))
+}
+
+// Completing an already completed task keeps its original `completed_at`. Completing an open
+// recurring task creates its next occurrence, which takes the rule over so that the completed
+// one can't spawn another.
+pub async fn complete_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Query(query): Query<CascadeQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, id, Role::Editor).await?;
+    let previous = sqlx::query_as!(TaskRow, "SELECT * FROM tasks WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::NotFound("task"))?;
+    let next = if previous.completed {
+        None
+    } else {
+        create_next_occurrence(&mut tx, user.id, &previous).await?
+    };
+    let row = sqlx::query_as!(
+        TaskRow,
+        "UPDATE tasks SET
+            completed = TRUE,
+            completed_at = COALESCE(completed_at, now()),
+            recurrence = CASE WHEN $2 THEN NULL ELSE recurrence END,
+            recurrence_tz = CASE WHEN $2 THEN NULL ELSE recurrence_tz END
+        WHERE id = $1
+        RETURNING *",
+        id,
+        next.is_some()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let mut rows = vec![row];
+    if query.cascade {
+        // Subtasks that were already completed are left alone.
+        rows.extend(
+            sqlx::query_as!(
+                TaskRow,
+                "WITH RECURSIVE subtree AS (
+                    SELECT id FROM tasks WHERE parent_id = $1
+                    UNION
+                    SELECT tasks.id FROM tasks JOIN subtree ON tasks.parent_id = subtree.id
+                )
+                UPDATE tasks SET completed = TRUE, completed_at = now()
+                WHERE id IN (SELECT id FROM subtree) AND NOT completed
+                RETURNING *",
+                id
+            )
+            .fetch_all(&mut *tx)
+            .await?,
+        );
+    }
+    let tasks = with_tags(&mut tx, rows).await?;
+    let newly_completed = if previous.completed {
+        &tasks[1..]
+    } else {
+        &tasks[..]
+    };
+    record_task_events(&mut tx, TaskEvent::Completed, newly_completed).await?;
+    let next_task = with_tags(&mut tx, next.into_iter().collect()).await?;
+    record_task_events(&mut tx, TaskEvent::Created, &next_task).await?;
+    tx.commit().await?;
+    let task = tasks.into_iter().next();
+    let next_task = next_task.into_iter().next();
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": task, "next_task": next_task }).to_string(),
+    ))
+}
+
+const DEFAULT_OCCURRENCE_LIMIT: usize = 10;
+const MAX_OCCURRENCE_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct OccurrencesQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Occurrence {
+    start_at: Option<DateTime<Utc>>,
+    due_at: DateTime<Utc>,
+}
+
+/// Previews the upcoming occurrences of a recurring task, starting with the task itself.
+pub async fn get_task_occurrences(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Query(query): Query<OccurrencesQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, id, Role::Viewer).await?;
+    let row = sqlx::query_as!(TaskRow, "SELECT * FROM tasks WHERE id = $1", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::NotFound("task"))?;
+    tx.commit().await?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_OCCURRENCE_LIMIT)
+        .clamp(1, MAX_OCCURRENCE_LIMIT);
+    let mut occurrences = Vec::new();
+    if let (Some((mut recurrence, tz)), Some(mut due_at)) = (task_recurrence(&row)?, row.due_at) {
+        let duration = row.start_at.map(|start_at| due_at - start_at);
+        loop {
+            occurrences.push(Occurrence {
+                start_at: duration.map(|duration| due_at - duration),
+                due_at,
+            });
+            if occurrences.len() == limit {
+                break;
+            }
+            let Some(next) = recurrence.next(due_at, tz) else {
+                break;
+            };
+            (due_at, recurrence) = next;
+        }
+    }
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": occurrences }).to_string(),
+    ))
+}
+
+pub async fn uncomplete_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, id, Role::Editor).await?;
+    let row = sqlx::query_as!(
+        TaskRow,
+        "UPDATE tasks SET completed = FALSE, completed_at = NULL WHERE id = $1 RETURNING *",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("task"))?;
+    let task = with_tags(&mut tx, vec![row]).await?;
+    record_task_events(&mut tx, TaskEvent::Updated, &task).await?;
+    tx.commit().await?;
+    let task = task.into_iter().next();
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": task }).to_string(),
+    ))
+}
+
+// Tasks with subtasks are only deleted, together with their subtree, when `cascade` is set.
+pub async fn delete_task(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Query(query): Query<CascadeQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    authorize_task(&mut tx, user.id, id, Role::Editor).await?;
+    if !query.cascade {
+        let has_children = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM tasks WHERE parent_id = $1) AS "exists!""#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if has_children {
+            return Err(AppError::Conflict(
+                "has_subtasks",
+                "task has subtasks; delete with cascade=true to remove them too".to_owned(),
+            ));
+        }
+    }
+
+    // Loaded before the delete, so the events can carry what was deleted.
+    let mut rows = vec![
+        sqlx::query_as!(TaskRow, "SELECT * FROM tasks WHERE id = $1", id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(AppError::NotFound("task"))?,
+    ];
+    if query.cascade {
+        rows.extend(load_descendants(&mut tx, id, None).await?);
+    }
+    let deleted = with_tags(&mut tx, rows).await?;
+
+    let result = sqlx::query!("DELETE FROM tasks WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("task"));
+    }
+    record_task_events(&mut tx, TaskEvent::Deleted, &deleted).await?;
+    tx.commit().await?;
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        as_user, body, data, insert_task, insert_team, insert_user, personal_workspace, status,
+    };
+
+    fn task_row(priority: Option<i32>, due_at: Option<DateTime<Utc>>) -> TaskRow {
+        TaskRow {
+            id: 7,
+            name: "Write tests".to_owned(),
+            priority,
+            completed: false,
+            completed_at: None,
+            start_at: None,
+            due_at,
+            list_id: 1,
+            parent_id: None,
+            owner_id: None,
+            workspace_id: None,
+            assignee_id: None,
+            recurrence: None,
+            recurrence_tz: None,
+        }
+    }
+
+    fn cursor_condition(keys: &[TaskSortKey], values: &[CursorValue]) -> String {
+        let mut builder = QueryBuilder::new("");
+        push_cursor_condition(&mut builder, keys, values);
+        builder.into_sql()
+    }
+
+    #[test]
+    fn cursor_round_trips_sort_key_values() {
+        let keys = parse_sort(Some("name,-due_at,priority")).unwrap();
+        let due_at = Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap();
+        let cursor = encode_cursor(&task_row(Some(2), Some(due_at)), &keys);
+
+        let values = decode_cursor(&cursor, &keys).unwrap();
+        assert!(matches!(&values[0], CursorValue::Text(name) if name == "Write tests"));
+        assert!(matches!(values[1], CursorValue::Timestamp(t) if t == due_at));
+        assert!(matches!(values[2], CursorValue::Int(2)));
+        assert!(matches!(values[3], CursorValue::Int(7)));
+    }
+
+    #[test]
+    fn cursor_round_trips_null_values() {
+        let keys = parse_sort(Some("priority,due_at")).unwrap();
+        let cursor = encode_cursor(&task_row(None, None), &keys);
+
+        let values = decode_cursor(&cursor, &keys).unwrap();
+        assert!(matches!(values[0], CursorValue::Null));
+        assert!(matches!(values[1], CursorValue::Null));
+        assert!(matches!(values[2], CursorValue::Int(7)));
+    }
+
+    #[test]
+    fn decode_cursor_rejects_cursors_for_other_sorts() {
+        let keys = parse_sort(None).unwrap();
+        let cursor = encode_cursor(&task_row(None, None), &keys);
+        assert!(decode_cursor(&cursor, &keys).is_some());
+
+        // Wrong number of keys, wrong value type, NULL for a non-nullable key, not base64.
+        assert!(decode_cursor(&cursor, &parse_sort(Some("priority")).unwrap()).is_none());
+        assert!(decode_cursor(&cursor, &parse_sort(Some("name")).unwrap()[..1]).is_none());
+        let null_id = URL_SAFE_NO_PAD.encode("[null]");
+        assert!(decode_cursor(&null_id, &keys).is_none());
+        assert!(decode_cursor("not a cursor!", &keys).is_none());
+    }
+
+    #[test]
+    fn cursor_condition_by_id() {
+        let keys = parse_sort(None).unwrap();
+        assert_eq!(
+            cursor_condition(&keys, &[CursorValue::Int(7)]),
+            " AND (FALSE OR (TRUE AND (id > $1)))"
+        );
+    }
+
+    #[test]
+    fn parse_sort_appends_id_as_tiebreaker() {
+        let keys = parse_sort(Some("-priority,name")).unwrap();
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| (key.column.as_sql(), key.descending))
+            .collect();
+        assert_eq!(keys, [("priority", true), ("name", false), ("id", false)]);
+
+        let keys = parse_sort(Some("-id,due_at")).unwrap();
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| (key.column.as_sql(), key.descending))
+            .collect();
+        assert_eq!(keys, [("id", true), ("due_at", false)]);
+    }
+
+    #[test]
+    fn parse_sort_rejects_unknown_and_repeated_fields() {
+        assert_eq!(parse_sort(Some("")).unwrap().len(), 1);
+        assert!(parse_sort(Some("owner_id")).is_err());
+        assert!(parse_sort(Some("name,-name")).is_err());
+        assert!(parse_sort(Some("--name")).is_err());
+    }
+
+    #[test]
+    fn cursor_condition_with_mixed_directions() {
+        let keys = parse_sort(Some("-priority,name")).unwrap();
+        let values = [
+            CursorValue::Int(3),
+            CursorValue::Text("b".to_owned()),
+            CursorValue::Int(7),
+        ];
+        assert_eq!(
+            cursor_condition(&keys, &values),
+            " AND (FALSE \
+            OR (TRUE AND (priority < $1 OR priority IS NULL)) \
+            OR (TRUE AND priority = $2 AND (name > $3)) \
+            OR (TRUE AND priority = $4 AND name = $5 AND (id > $6)))"
+        );
+    }
+
+    #[test]
+    fn cursor_condition_with_descending_nullable_key() {
+        let keys = parse_sort(Some("-due_at")).unwrap();
+        let due_at = Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap();
+        let values = [CursorValue::Timestamp(due_at), CursorValue::Int(7)];
+        // NULLs come last in either direction, so they are past any non-NULL cursor value.
+        assert_eq!(
+            cursor_condition(&keys, &values),
+            " AND (FALSE \
+            OR (TRUE AND (due_at < $1 OR due_at IS NULL)) \
+            OR (TRUE AND due_at = $2 AND (id > $3)))"
+        );
+    }
+
+    #[test]
+    fn cursor_condition_after_null_value() {
+        // Only rows that are also NULL in the key can follow a NULL cursor value.
+        for sort in ["priority", "-priority"] {
+            let keys = parse_sort(Some(sort)).unwrap();
+            let values = [CursorValue::Null, CursorValue::Int(7)];
+            assert_eq!(
+                cursor_condition(&keys, &values),
+                " AND (FALSE OR (TRUE AND priority IS NULL AND (id > $1)))"
+            );
+        }
+    }
+
+    #[test]
+    fn patch_tells_missing_fields_from_nulls() {
+        let task: UpdateTaskRequest =
+            body(json!({ "priority": null, "due_at": "2030-01-01T09:00:00Z" }));
+        assert_eq!(task.priority, Some(None));
+        assert!(matches!(task.due_at, Some(Some(_))));
+        assert_eq!(task.start_at, None);
+        assert_eq!(task.parent_id, None);
+        assert_eq!(task.name, None);
+    }
+
+    // The error code `check_parent` rejects the parent with, if any.
+    async fn parent_error(
+        db_pool: &PgPool,
+        workspace_id: i32,
+        id: Option<i32>,
+        parent_id: i32,
+    ) -> Option<&'static str> {
+        let mut conn = db_pool.acquire().await.unwrap();
+        match check_parent(&mut conn, workspace_id, id, parent_id).await {
+            Ok(()) => None,
+            Err(AppError::Validation(errors)) => Some(errors[0].code),
+            Err(_) => panic!("check_parent failed"),
+        }
+    }
+
+    #[sqlx::test]
+    async fn check_parent_rejects_cycles_and_other_workspaces(db_pool: PgPool) {
+        let alice = insert_user(&db_pool, "alice@example.com").await;
+        let bob = insert_user(&db_pool, "bob@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, alice).await;
+        let other_workspace_id = personal_workspace(&db_pool, bob).await;
+        let root = insert_task(&db_pool, workspace_id, "Root").await;
+        let child = insert_task(&db_pool, workspace_id, "Child").await;
+        let grandchild = insert_task(&db_pool, workspace_id, "Grandchild").await;
+        let other = insert_task(&db_pool, other_workspace_id, "Other").await;
+        for (id, parent_id) in [(child, root), (grandchild, child)] {
+            sqlx::query!(
+                "UPDATE tasks SET parent_id = $2 WHERE id = $1",
+                id,
+                parent_id
+            )
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        }
+
+        let cases = [
+            ("itself", Some(root), root, Some("cycle")),
+            ("its child", Some(root), child, Some("cycle")),
+            ("its grandchild", Some(root), grandchild, Some("cycle")),
+            ("another workspace", Some(root), other, Some("not_found")),
+            (
+                "another workspace, new task",
+                None,
+                other,
+                Some("not_found"),
+            ),
+            ("an ancestor", Some(grandchild), root, None),
+            ("a task, new task", None, grandchild, None),
+        ];
+        for (name, id, parent_id, expected) in cases {
+            assert_eq!(
+                parent_error(&db_pool, workspace_id, id, parent_id).await,
+                expected,
+                "parent is {name}"
+            );
+        }
+    }
+
+    struct StoredTask {
+        name: String,
+        priority: Option<i32>,
+        due_at: Option<DateTime<Utc>>,
+        tags: Vec<String>,
+    }
+
+    async fn stored_task(db_pool: &PgPool, id: i32) -> StoredTask {
+        sqlx::query_as!(
+            StoredTask,
+            r#"SELECT name, priority, due_at, ARRAY(
+                SELECT tags.name FROM task_tags JOIN tags ON tags.id = task_tags.tag_id
+                WHERE task_tags.task_id = tasks.id ORDER BY tags.name
+            ) AS "tags!"
+            FROM tasks WHERE id = $1"#,
+            id
+        )
+        .fetch_one(db_pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn patch_keeps_missing_fields_and_clears_nulls(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let created = create_task(
+            State(db_pool.clone()),
+            as_user(user_id),
+            Valid(body(json!({
+                "name": "Shop",
+                "priority": 2,
+                "due_at": "2030-01-01T09:00:00Z",
+                "tags": ["errands"]
+            }))),
+        )
+        .await;
+        let id = data(created)["id"].as_i64().unwrap() as i32;
+
+        let patch = |patch| {
+            update_task(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Path(id),
+                Valid(body(patch)),
+            )
+        };
+        assert_eq!(
+            status(patch(json!({ "name": "Shop for dinner" })).await),
+            StatusCode::OK
+        );
+        let task = stored_task(&db_pool, id).await;
+        assert_eq!(task.name, "Shop for dinner");
+        assert_eq!(task.priority, Some(2));
+        assert!(task.due_at.is_some());
+        assert_eq!(task.tags, ["errands"]);
+
+        assert_eq!(
+            status(patch(json!({ "priority": null })).await),
+            StatusCode::OK
+        );
+        let task = stored_task(&db_pool, id).await;
+        assert_eq!(task.name, "Shop for dinner");
+        assert_eq!(task.priority, None);
+        assert!(task.due_at.is_some());
+        assert_eq!(task.tags, ["errands"]);
+    }
+
+    #[sqlx::test]
+    async fn put_replaces_every_field(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let created = create_task(
+            State(db_pool.clone()),
+            as_user(user_id),
+            Valid(body(json!({
+                "name": "Shop",
+                "priority": 2,
+                "due_at": "2030-01-01T09:00:00Z",
+                "tags": ["errands"]
+            }))),
+        )
+        .await;
+        let id = data(created)["id"].as_i64().unwrap() as i32;
+        let list_id = sqlx::query_scalar!("SELECT list_id FROM tasks WHERE id = $1", id)
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+        let mut replacement = json!({
+            "name": "Cook",
+            "priority": null,
+            "start_at": null,
+            "due_at": null,
+            "tags": [],
+            "list_id": list_id,
+            "parent_id": null,
+            "assignee_id": null,
+            "recurrence": null,
+            "recurrence_tz": null
+        });
+
+        let replaced = replace_task(
+            State(db_pool.clone()),
+            as_user(user_id),
+            Path(id),
+            Valid(body(replacement.clone())),
+        )
+        .await;
+        assert_eq!(status(replaced), StatusCode::OK);
+        let task = stored_task(&db_pool, id).await;
+        assert_eq!(task.name, "Cook");
+        assert_eq!(task.priority, None);
+        assert_eq!(task.due_at, None);
+        assert!(task.tags.is_empty());
+
+        // Leaving a field out is an error rather than a way of keeping it.
+        replacement.as_object_mut().unwrap().remove("priority");
+        assert!(serde_json::from_value::<ReplaceTaskRequest>(replacement).is_err());
+    }
+
+    #[sqlx::test]
+    async fn task_operations_follow_workspace_roles(db_pool: PgPool) {
+        let owner = insert_user(&db_pool, "owner@example.com").await;
+        let editor = insert_user(&db_pool, "editor@example.com").await;
+        let viewer = insert_user(&db_pool, "viewer@example.com").await;
+        let outsider = insert_user(&db_pool, "outsider@example.com").await;
+        let members = [(editor, Role::Editor), (viewer, Role::Viewer)];
+        let workspace_id = insert_team(&db_pool, owner, &members).await;
+
+        // Expected statuses of get, create, update and delete.
+        const OK: StatusCode = StatusCode::OK;
+        const FORBIDDEN: StatusCode = StatusCode::FORBIDDEN;
+        const NOT_FOUND: StatusCode = StatusCode::NOT_FOUND;
+        let cases = [
+            ("owner", owner, [OK, OK, OK, OK]),
+            ("editor", editor, [OK, OK, OK, OK]),
+            ("viewer", viewer, [OK, FORBIDDEN, FORBIDDEN, FORBIDDEN]),
+            ("non-member", outsider, [NOT_FOUND; 4]),
+        ];
+        for (name, user_id, expected) in cases {
+            let created = create_task(
+                State(db_pool.clone()),
+                as_user(owner),
+                Valid(body(
+                    json!({ "name": "Shared", "workspace_id": workspace_id }),
+                )),
+            )
+            .await;
+            let task_id = data(created)["id"].as_i64().unwrap() as i32;
+
+            let get = get_task(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Path(task_id),
+                Query(body(json!({}))),
+            )
+            .await;
+            let create = create_task(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Valid(body(json!({ "name": "New", "workspace_id": workspace_id }))),
+            )
+            .await;
+            let update = update_task(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Path(task_id),
+                Valid(body(json!({ "name": "Renamed" }))),
+            )
+            .await;
+            let delete = delete_task(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Path(task_id),
+                Query(body(json!({}))),
+            )
+            .await;
+            let actual = [status(get), status(create), status(update), status(delete)];
+            assert_eq!(actual, expected, "{name}: get, create, update, delete");
+        }
+    }
+
+    // Superusers are exempt from row-level security, so this acts as an ordinary role, as the
+    // server does. Roles belong to the whole cluster, which tests share.
+    async fn begin_as_app(db_pool: &PgPool, user_id: i32) -> sqlx::Transaction<'static, Postgres> {
+        sqlx::query(
+            "DO $$ BEGIN
+                CREATE ROLE todolist_rls_test NOLOGIN;
+            EXCEPTION WHEN duplicate_object OR unique_violation THEN NULL;
+            END $$",
+        )
+        .execute(db_pool)
+        .await
+        .unwrap();
+        for grant in [
+            "GRANT ALL ON ALL TABLES IN SCHEMA public TO todolist_rls_test",
+            "GRANT ALL ON ALL SEQUENCES IN SCHEMA public TO todolist_rls_test",
+        ] {
+            sqlx::query(grant).execute(db_pool).await.unwrap();
+        }
+        let mut tx = as_user(user_id).begin(db_pool).await.unwrap();
+        sqlx::query("SET LOCAL ROLE todolist_rls_test")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx
+    }
+
+    #[sqlx::test]
+    async fn row_security_hides_other_workspaces(db_pool: PgPool) {
+        let alice = insert_user(&db_pool, "alice@example.com").await;
+        let bob = insert_user(&db_pool, "bob@example.com").await;
+        let created = create_task(
+            State(db_pool.clone()),
+            as_user(bob),
+            Valid(body(
+                json!({ "name": "Private", "due_at": "2030-01-01T09:00:00Z" }),
+            )),
+        )
+        .await;
+        let task_id = data(created)["id"].as_i64().unwrap() as i32;
+        let workspace_id = personal_workspace(&db_pool, bob).await;
+        let mut conn = db_pool.acquire().await.unwrap();
+        sqlx::query!(
+            "INSERT INTO comments (task_id, author_id, body, body_html) VALUES ($1, $2, 'Hi', 'Hi')",
+            task_id,
+            bob
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO attachments (task_id, uploader_id, file_name, content_type, size, storage_key)
+            VALUES ($1, $2, 'a.txt', 'text/plain', 1, 'rls-test')",
+            task_id,
+            bob
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO reminders (task_id, user_id, offset_minutes) VALUES ($1, $2, 10)",
+            task_id,
+            bob
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        // Counts bob's task and the rows hanging off it, as seen by `user_id`.
+        async fn visible(
+            db_pool: &PgPool,
+            user_id: i32,
+            task_id: i32,
+            workspace_id: i32,
+        ) -> [i64; 5] {
+            let mut tx = begin_as_app(db_pool, user_id).await;
+            let counts = sqlx::query!(
+                r#"SELECT
+                    (SELECT COUNT(*) FROM tasks WHERE id = $1) AS "tasks!",
+                    (SELECT COUNT(*) FROM comments WHERE task_id = $1) AS "comments!",
+                    (SELECT COUNT(*) FROM attachments WHERE task_id = $1) AS "attachments!",
+                    (SELECT COUNT(*) FROM reminders WHERE task_id = $1) AS "reminders!",
+                    (SELECT COUNT(*) FROM task_events WHERE workspace_id = $2) AS "task_events!""#,
+                task_id,
+                workspace_id
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+            [
+                counts.tasks,
+                counts.comments,
+                counts.attachments,
+                counts.reminders,
+                counts.task_events,
+            ]
+        }
+        assert_eq!(visible(&db_pool, bob, task_id, workspace_id).await, [1; 5]);
+        assert_eq!(
+            visible(&db_pool, alice, task_id, workspace_id).await,
+            [0; 5]
+        );
+
+        let mut tx = begin_as_app(&db_pool, alice).await;
+        let writes = [
+            sqlx::query!("UPDATE tasks SET name = 'Taken' WHERE id = $1", task_id)
+                .execute(&mut *tx)
+                .await,
+            sqlx::query!(
+                "UPDATE comments SET body = 'Taken' WHERE task_id = $1",
+                task_id
+            )
+            .execute(&mut *tx)
+            .await,
+            sqlx::query!("DELETE FROM attachments WHERE task_id = $1", task_id)
+                .execute(&mut *tx)
+                .await,
+            sqlx::query!("DELETE FROM reminders WHERE task_id = $1", task_id)
+                .execute(&mut *tx)
+                .await,
+            sqlx::query!(
+                "DELETE FROM task_events WHERE workspace_id = $1",
+                workspace_id
+            )
+            .execute(&mut *tx)
+            .await,
+            sqlx::query!("DELETE FROM tasks WHERE id = $1", task_id)
+                .execute(&mut *tx)
+                .await,
+        ];
+        for (i, result) in writes.into_iter().enumerate() {
+            assert_eq!(result.unwrap().rows_affected(), 0, "write {i}");
+        }
+        let comment = sqlx::query!(
+            "INSERT INTO comments (task_id, author_id, body, body_html) VALUES ($1, $2, 'Hi', 'Hi')",
+            task_id,
+            alice
+        )
+        .execute(&mut *tx)
+        .await;
+        assert!(comment.is_err(), "alice commented on bob's task");
+        drop(tx);
+
+        let name = sqlx::query_scalar!("SELECT name FROM tasks WHERE id = $1", task_id)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(name, "Private");
+    }
+
+    #[sqlx::test]
+    async fn member_removal_and_list_deletion_record_events(db_pool: PgPool) {
+        let owner = insert_user(&db_pool, "owner@example.com").await;
+        let member = insert_user(&db_pool, "member@example.com").await;
+        let workspace_id = insert_team(&db_pool, owner, &[(member, Role::Editor)]).await;
+        let mut conn = db_pool.acquire().await.unwrap();
+        let list_id = sqlx::query_scalar!(
+            "INSERT INTO lists (name, workspace_id) VALUES ('Errands', $1) RETURNING id",
+            workspace_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        let task_id = sqlx::query_scalar!(
+            "INSERT INTO tasks (name, list_id, workspace_id, assignee_id)
+            VALUES ('Shop', $1, $2, $3) RETURNING id",
+            list_id,
+            workspace_id,
+            member
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+
+        let removed = crate::workspaces::remove_member(
+            State(db_pool.clone()),
+            as_user(owner),
+            Path((workspace_id, member)),
+        )
+        .await;
+        assert_eq!(status(removed), StatusCode::OK);
+        let deleted =
+            crate::lists::delete_list(State(db_pool.clone()), as_user(owner), Path(list_id)).await;
+        assert_eq!(status(deleted), StatusCode::OK);
+
+        let events = sqlx::query!(
+            r#"SELECT event, payload->>'assignee_id' AS assignee_id FROM task_events
+            WHERE task_id = $1 ORDER BY id"#,
+            task_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        let events: Vec<_> = events
+            .into_iter()
+            .map(|event| (event.event, event.assignee_id))
+            .collect();
+        assert_eq!(
+            events,
+            [("updated".to_owned(), None), ("deleted".to_owned(), None)]
+        );
+    }
+
+    #[sqlx::test]
+    async fn list_deletion_records_events_for_subtasks_in_other_lists(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let list = crate::lists::create_list(
+            State(db_pool.clone()),
+            as_user(user_id),
+            Valid(body(json!({ "name": "Errands" }))),
+        )
+        .await;
+        let list_id = data(list)["id"].as_i64().unwrap() as i32;
+        let parent = create_list_task(
+            State(db_pool.clone()),
+            as_user(user_id),
+            Path(list_id),
+            Valid(body(json!({ "name": "Shop" }))),
+        )
+        .await;
+        let parent_id = data(parent)["id"].as_i64().unwrap();
+        // Created in the default list.
+        let subtask = create_task(
+            State(db_pool.clone()),
+            as_user(user_id),
+            Valid(body(json!({ "name": "Buy milk", "parent_id": parent_id }))),
+        )
+        .await;
+        let subtask_id = data(subtask)["id"].as_i64().unwrap() as i32;
+
+        let deleted =
+            crate::lists::delete_list(State(db_pool.clone()), as_user(user_id), Path(list_id))
+                .await;
+        assert_eq!(status(deleted), StatusCode::OK);
+        let events = sqlx::query_scalar!(
+            "SELECT event FROM task_events WHERE task_id = $1 ORDER BY id",
+            subtask_id
+        )
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+        assert_eq!(events, ["created", "deleted"]);
+    }
+
+    #[sqlx::test]
+    async fn viewers_cannot_add_tasks_to_lists(db_pool: PgPool) {
+        let owner = insert_user(&db_pool, "owner@example.com").await;
+        let viewer = insert_user(&db_pool, "viewer@example.com").await;
+        let workspace_id = insert_team(&db_pool, owner, &[(viewer, Role::Viewer)]).await;
+        let list_id = sqlx::query_scalar!(
+            "SELECT id FROM lists WHERE workspace_id = $1 AND is_default",
+            workspace_id
+        )
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+
+        for (user_id, expected) in [(viewer, StatusCode::FORBIDDEN), (owner, StatusCode::OK)] {
+            let created = create_list_task(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Path(list_id),
+                Valid(body(json!({ "name": "Shop" }))),
+            )
+            .await;
+            assert_eq!(status(created), expected);
+        }
+    }
+
+    #[sqlx::test]
+    async fn tasks_created_in_a_list_join_its_workspace(db_pool: PgPool) {
+        let owner = insert_user(&db_pool, "owner@example.com").await;
+        let editor = insert_user(&db_pool, "editor@example.com").await;
+        let outsider = insert_user(&db_pool, "outsider@example.com").await;
+        let workspace_id = insert_team(&db_pool, owner, &[(editor, Role::Editor)]).await;
+        let list_id = sqlx::query_scalar!(
+            "SELECT id FROM lists WHERE workspace_id = $1 AND is_default",
+            workspace_id
+        )
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+
+        let created = create_task(
+            State(db_pool.clone()),
+            as_user(editor),
+            Valid(body(json!({ "name": "Shop", "list_id": list_id }))),
+        )
+        .await;
+        let task_id = data(created)["id"].as_i64().unwrap() as i32;
+        let task_workspace_id = sqlx::query_scalar!(
+            r#"SELECT workspace_id AS "workspace_id!" FROM tasks WHERE id = $1"#,
+            task_id
+        )
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+        assert_eq!(task_workspace_id, workspace_id);
+
+        let created = create_task(
+            State(db_pool.clone()),
+            as_user(outsider),
+            Valid(body(json!({ "name": "Shop", "list_id": list_id }))),
+        )
+        .await;
+        assert_eq!(status(created), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[sqlx::test]
+    async fn out_of_range_pages_are_rejected(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let cases = [
+            (json!({ "limit": 0 }), StatusCode::UNPROCESSABLE_ENTITY),
+            (json!({ "limit": -1 }), StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                json!({ "limit": MAX_PAGE_LIMIT + 1 }),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (json!({ "offset": -1 }), StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                json!({ "limit": MAX_PAGE_LIMIT, "offset": 0 }),
+                StatusCode::OK,
+            ),
+        ];
+        for (query, expected) in cases {
+            let fetched = get_tasks(
+                State(db_pool.clone()),
+                as_user(user_id),
+                Query(body(query.clone())),
+            )
+            .await;
+            assert_eq!(status(fetched), expected, "{query}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Fixtures shared by the database tests.
+
+use axum::{http::StatusCode, response::IntoResponse};
+use chrono::Duration;
+use serde::de::DeserializeOwned;
+use sqlx::PgPool;
+
+use crate::auth::{AuthConfig, AuthUser, JwtKeys};
+use crate::error::AppError;
+use crate::workspaces::{insert_workspace, personal_workspace_id, Role};
+
+/// Inserts a user without a usable password, along with their personal workspace.
+pub async fn insert_user(db_pool: &PgPool, email: &str) -> i32 {
+    let mut conn = db_pool.acquire().await.unwrap();
+    let id = sqlx::query_scalar!(
+        "INSERT INTO users (email, password_hash) VALUES ($1, '') RETURNING id",
+        email
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    insert_workspace(&mut conn, id, "Personal", true)
+        .await
+        .unwrap();
+    id
+}
+
+/// The user's personal workspace.
+pub async fn personal_workspace(db_pool: &PgPool, user_id: i32) -> i32 {
+    let mut conn = db_pool.acquire().await.unwrap();
+    personal_workspace_id(&mut conn, user_id).await.unwrap()
+}
+
+/// Inserts a shared workspace owned by `owner_id`, with these other members.
+pub async fn insert_team(db_pool: &PgPool, owner_id: i32, members: &[(i32, Role)]) -> i32 {
+    let mut conn = db_pool.acquire().await.unwrap();
+    let id = insert_workspace(&mut conn, owner_id, "Team", false)
+        .await
+        .unwrap();
+    for &(user_id, role) in members {
+        sqlx::query!(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, $3)",
+            id,
+            user_id,
+            role.as_str()
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    }
+    id
+}
+
+/// Inserts a task into the workspace's default list, bypassing the handlers' checks.
+pub async fn insert_task(db_pool: &PgPool, workspace_id: i32, name: &str) -> i32 {
+    sqlx::query_scalar!(
+        "INSERT INTO tasks (name, list_id, workspace_id)
+        SELECT $2, id, $1 FROM lists WHERE workspace_id = $1 AND is_default
+        RETURNING id",
+        workspace_id,
+        name
+    )
+    .fetch_one(db_pool)
+    .await
+    .unwrap()
+}
+
+/// The user as signed in interactively.
+pub fn as_user(id: i32) -> AuthUser {
+    AuthUser {
+        id,
+        api_key_id: None,
+    }
+}
+
+/// Signs tokens with a fixed HS256 secret.
+pub fn auth_config() -> AuthConfig {
+    AuthConfig {
+        session_ttl: Duration::hours(1),
+        access_token_ttl: Duration::minutes(15),
+        refresh_token_ttl: Duration::hours(1),
+        jwt: JwtKeys::hs256("test secret"),
+    }
+}
+
+/// The status a handler's result is answered with.
+pub fn status(result: Result<(StatusCode, String), AppError>) -> StatusCode {
+    match result {
+        Ok((status, _)) => status,
+        Err(e) => e.into_response().status(),
+    }
+}
+
+/// The `data` of a successful handler response.
+pub fn data(result: Result<(StatusCode, String), AppError>) -> serde_json::Value {
+    let response = match result {
+        Ok((_, response)) => response,
+        Err(e) => panic!("request failed with {}", e.into_response().status()),
+    };
+    let mut response: serde_json::Value = serde_json::from_str(&response).unwrap();
+    response["data"].take()
+}
+
+/// A request body or query built from JSON.
+pub fn body<T: DeserializeOwned>(value: serde_json::Value) -> T {
+    serde_json::from_value(value).unwrap()
+}
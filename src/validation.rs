@@ -0,0 +1,138 @@
+use std::{collections::HashSet, ops::RangeInclusive};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequest, Request},
+};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::{AppError, FieldError, Json};
+
+#[derive(Clone)]
+pub struct ValidationRules {
+    pub name_max_length: usize,
+    pub tag_name_max_length: usize,
+    pub comment_max_length: usize,
+    pub priority_range: RangeInclusive<i32>,
+}
+
+impl ValidationRules {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(key: &str, default: T) -> T {
+            std::env::var(key).map_or(default, |value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("{key} is invalid."))
+            })
+        }
+        Self {
+            name_max_length: var("TASK_NAME_MAX_LENGTH", 200),
+            tag_name_max_length: var("TAG_NAME_MAX_LENGTH", 50),
+            comment_max_length: var("COMMENT_MAX_LENGTH", 10_000),
+            priority_range: var("TASK_PRIORITY_MIN", 0)..=var("TASK_PRIORITY_MAX", 100),
+        }
+    }
+}
+
+/// Collects every failing field of a payload so they can be reported together.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    /// NFC-normalizes and trims `value` in place, then requires it to be non-empty and at most
+    /// `max_length` characters.
+    pub fn text(&mut self, field: &'static str, value: &mut String, max_length: usize) {
+        *value = value.nfc().collect::<String>().trim().to_owned();
+        if value.is_empty() {
+            self.fail(field, "required", "must not be empty".to_owned());
+        } else if value.chars().count() > max_length {
+            self.fail(
+                field,
+                "too_long",
+                format!("must be at most {max_length} characters"),
+            );
+        }
+    }
+
+    /// Applies `text` to every element, then drops case-insensitive duplicates.
+    pub fn distinct_texts(
+        &mut self,
+        field: &'static str,
+        values: &mut Vec<String>,
+        max_length: usize,
+    ) {
+        let mut seen = HashSet::new();
+        for value in values.iter_mut() {
+            self.text(field, value, max_length);
+        }
+        values.retain(|value| seen.insert(value.to_lowercase()));
+    }
+
+    pub fn range(&mut self, field: &'static str, value: i32, range: &RangeInclusive<i32>) {
+        if !range.contains(&value) {
+            self.fail(
+                field,
+                "out_of_range",
+                format!("must be between {} and {}", range.start(), range.end()),
+            );
+        }
+    }
+
+    /// Requires `start_at` to be no later than `due_at` when both are set.
+    pub fn dates(
+        &mut self,
+        field: &'static str,
+        start_at: Option<DateTime<Utc>>,
+        due_at: Option<DateTime<Utc>>,
+    ) {
+        if let (Some(start_at), Some(due_at)) = (start_at, due_at) {
+            if start_at > due_at {
+                self.fail(field, "after_due", "must not be after due_at".to_owned());
+            }
+        }
+    }
+
+    pub fn fail(&mut self, field: &'static str, code: &'static str, message: String) {
+        self.errors.push(FieldError {
+            field,
+            code,
+            message,
+        });
+    }
+
+    pub fn finish(self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(self.errors))
+        }
+    }
+}
+
+pub trait Validate {
+    /// Normalizes the payload in place and checks it against `rules`.
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError>;
+}
+
+/// JSON body extractor that only succeeds once the payload passes `Validate`.
+pub struct Valid<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Valid<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    ValidationRules: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(mut payload) = Json::<T>::from_request(req, state).await?;
+        payload.validate(&ValidationRules::from_ref(state))?;
+        Ok(Self(payload))
+    }
+}
@@ -0,0 +1,737 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::PgPool;
+
+use crate::auth::{generate_token, AuthUser};
+use crate::error::{AppError, Path, Query};
+use crate::events::TaskEvent;
+use crate::tasks::deserialize_some;
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+use crate::worker::run_batches;
+use crate::workspaces::{require_role, Role};
+
+const URL_MAX_LENGTH: usize = 2000;
+const SECRET_LENGTH_RANGE: std::ops::RangeInclusive<usize> = 16..=256;
+
+const DEFAULT_DELIVERY_LIMIT: i64 = 50;
+const MAX_DELIVERY_LIMIT: i64 = 100;
+
+// Deliveries claimed per round trip, and attempts per delivery before giving up. Retries back
+// off exponentially from the base delay, up to the maximum: 30s, 1m, 2m, ... 1h.
+const DELIVERY_BATCH_SIZE: i64 = 50;
+const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+const RETRY_BASE_SECONDS: f64 = 30.0;
+const RETRY_MAX_SECONDS: f64 = 60.0 * 60.0;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+// How long claimed deliveries are held back from other servers. Longer than sending a batch,
+// which takes about `DELIVERY_TIMEOUT`, and recording the results.
+const CLAIM_SECONDS: f64 = 60.0;
+
+// Events, and with them the delivery log, are kept this long, and removed this many at a time.
+const EVENT_RETENTION_DAYS: i32 = 30;
+const EVENT_REMOVAL_BATCH_SIZE: i64 = 1000;
+
+#[derive(Serialize)]
+pub struct WebhookRow {
+    pub id: i32,
+    pub workspace_id: i32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn get_webhooks(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, workspace_id, Role::Owner).await?;
+    let rows = sqlx::query_as!(
+        WebhookRow,
+        "SELECT id, workspace_id, url, events, created_by, created_at FROM webhooks
+        WHERE workspace_id = $1
+        ORDER BY id",
+        workspace_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+fn check_url(validator: &mut Validator, url: &mut String) {
+    *url = url.trim().to_owned();
+    let valid = url.len() <= URL_MAX_LENGTH
+        && reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    if !valid {
+        validator.fail(
+            "url",
+            "invalid",
+            format!("must be an http or https URL of at most {URL_MAX_LENGTH} characters"),
+        );
+    }
+}
+
+fn check_events(validator: &mut Validator, events: &mut Vec<TaskEvent>) {
+    let mut seen = Vec::new();
+    events.retain(|event| {
+        let first = !seen.contains(event);
+        seen.push(*event);
+        first
+    });
+    if events.is_empty() {
+        validator.fail("events", "required", "must not be empty".to_owned());
+    }
+}
+
+fn check_secret(validator: &mut Validator, secret: &str) {
+    if !SECRET_LENGTH_RANGE.contains(&secret.len()) {
+        validator.fail(
+            "secret",
+            "out_of_range",
+            format!(
+                "must be between {} and {} characters",
+                SECRET_LENGTH_RANGE.start(),
+                SECRET_LENGTH_RANGE.end()
+            ),
+        );
+    }
+}
+
+fn event_names(events: &[TaskEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|event| event.as_str().to_owned())
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct CreateWebhookRequest {
+    url: String,
+    events: Vec<TaskEvent>,
+    /// Generated when omitted.
+    secret: Option<String>,
+}
+
+impl Validate for CreateWebhookRequest {
+    fn validate(&mut self, _rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        check_url(&mut validator, &mut self.url);
+        check_events(&mut validator, &mut self.events);
+        if let Some(secret) = &self.secret {
+            check_secret(&mut validator, secret);
+        }
+        validator.finish()
+    }
+}
+
+// The secret is only returned here, like an API key.
+pub async fn create_webhook(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    Valid(webhook): Valid<CreateWebhookRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, workspace_id, Role::Owner).await?;
+    let secret = webhook.secret.unwrap_or_else(generate_token);
+    let row = sqlx::query_as!(
+        WebhookRow,
+        "INSERT INTO webhooks (workspace_id, url, secret, events, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, workspace_id, url, events, created_by, created_at",
+        workspace_id,
+        webhook.url,
+        secret,
+        &event_names(&webhook.events),
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": { "secret": secret, "webhook": row } }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateWebhookRequest {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    url: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    events: Option<Vec<TaskEvent>>,
+    /// Rotates the secret; deliveries still pending are signed with the new one.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    secret: Option<String>,
+}
+
+impl Validate for UpdateWebhookRequest {
+    fn validate(&mut self, _rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        if let Some(url) = &mut self.url {
+            check_url(&mut validator, url);
+        }
+        if let Some(events) = &mut self.events {
+            check_events(&mut validator, events);
+        }
+        if let Some(secret) = &self.secret {
+            check_secret(&mut validator, secret);
+        }
+        validator.finish()
+    }
+}
+
+pub async fn update_webhook(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    Valid(webhook): Valid<UpdateWebhookRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, workspace_id, Role::Owner).await?;
+    let events = webhook.events.as_deref().map(event_names);
+    let row = sqlx::query_as!(
+        WebhookRow,
+        "UPDATE webhooks SET
+            url = COALESCE($3, url),
+            events = COALESCE($4, events),
+            secret = COALESCE($5, secret)
+        WHERE id = $1 AND workspace_id = $2
+        RETURNING id, workspace_id, url, events, created_by, created_at",
+        id,
+        workspace_id,
+        webhook.url,
+        events.as_deref(),
+        webhook.secret
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("webhook"))?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+pub async fn delete_webhook(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, workspace_id, Role::Owner).await?;
+    let result = sqlx::query!(
+        "DELETE FROM webhooks WHERE id = $1 AND workspace_id = $2",
+        id,
+        workspace_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("webhook"));
+    }
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct DeliveriesQuery {
+    limit: Option<i64>,
+    /// Only deliveries older than this one, for paging back through the log.
+    before: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct DeliveryRow {
+    id: i64,
+    event_id: i64,
+    event: String,
+    task_id: i32,
+    status: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+/// The webhook's delivery log, newest first.
+pub async fn get_webhook_deliveries(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, workspace_id, Role::Owner).await?;
+    sqlx::query_scalar!(
+        "SELECT id FROM webhooks WHERE id = $1 AND workspace_id = $2",
+        id,
+        workspace_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("webhook"))?;
+    let rows = sqlx::query_as!(
+        DeliveryRow,
+        "SELECT webhook_deliveries.id, event_id, task_events.event, task_events.task_id, status,
+            attempts, next_attempt_at, response_status, last_error, webhook_deliveries.created_at,
+            delivered_at
+        FROM webhook_deliveries JOIN task_events ON task_events.id = webhook_deliveries.event_id
+        WHERE webhook_id = $1 AND ($2::BIGINT IS NULL OR webhook_deliveries.id < $2)
+        ORDER BY webhook_deliveries.id DESC
+        LIMIT $3",
+        id,
+        query.before,
+        query
+            .limit
+            .unwrap_or(DEFAULT_DELIVERY_LIMIT)
+            .clamp(1, MAX_DELIVERY_LIMIT)
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+// Whether an address is on the public internet, rather than loopback, a private or link-local
+// network, or a range reserved for some other use. Webhooks may not target the others, since
+// their requests come from inside the server's network.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_address(IpAddr::V4(ip));
+            }
+            // NAT64 and 6to4 addresses reach the IPv4 address they embed.
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let embedded = (u32::from(segments[6]) << 16) | u32::from(segments[7]);
+                return is_public_address(IpAddr::V4(Ipv4Addr::from(embedded)));
+            }
+            if segments[0] == 0x2002 {
+                let embedded = (u32::from(segments[1]) << 16) | u32::from(segments[2]);
+                return is_public_address(IpAddr::V4(Ipv4Addr::from(embedded)));
+            }
+            // Teredo addresses embed the IPv4 addresses of both a server and a client behind NAT,
+            // either of which a relay may forward to, so none are allowed.
+            if segments[..2] == [0x2001, 0] {
+                return false;
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || ip == Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0)
+                || segments[0] & 0xfe00 == 0xfc00
+                || segments[0] & 0xffc0 == 0xfe80
+                || segments[0] == 0x2001 && segments[1] == 0x0db8)
+        }
+    }
+}
+
+// Resolves webhook hosts to their public addresses only. Resolving when connecting, rather
+// than checking the URL when the webhook is saved, also covers names that change what they
+// point to later.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_address(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// Rejects URLs whose host is a non-public IP address. Host names are checked by
+// `PublicResolver` instead, since addresses in URLs are connected to without resolving them.
+fn check_target(url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let host = url.host_str().unwrap_or_default();
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() else {
+        return Ok(());
+    };
+    if !is_public_address(ip) {
+        return Err(format!("{ip} is not a public address"));
+    }
+    Ok(())
+}
+
+/// Sends pending webhook deliveries, polling every `interval`. Deliveries are claimed with
+/// `FOR UPDATE SKIP LOCKED`, so several servers can run this side by side. Only public
+/// addresses are delivered to, unless `allow_private_targets` is set.
+pub async fn deliver_webhooks(db_pool: PgPool, interval: Duration, allow_private_targets: bool) {
+    // Redirects aren't followed, since the signature was made for the configured URL. Proxies
+    // aren't used either, since they would resolve the host themselves.
+    let mut client = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy();
+    if !allow_private_targets {
+        client = client.dns_resolver(Arc::new(PublicResolver));
+    }
+    let client = client
+        .build()
+        .expect("Failed to build the webhook HTTP client.");
+    tokio::join!(
+        run_batches("deliver webhooks", interval, DELIVERY_BATCH_SIZE, || {
+            deliver_webhooks_batch(&db_pool, &client, allow_private_targets)
+        }),
+        run_batches(
+            "remove old task events",
+            interval,
+            EVENT_REMOVAL_BATCH_SIZE,
+            || remove_old_events(&db_pool)
+        ),
+    );
+}
+
+async fn remove_old_events(db_pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    sqlx::query_scalar!("SELECT set_config('app.bypass_rls', 'on', TRUE)")
+        .fetch_one(&mut *tx)
+        .await?;
+    let result = sqlx::query!(
+        "DELETE FROM task_events WHERE id IN (
+            SELECT id FROM task_events
+            WHERE created_at < now() - make_interval(days => $1)
+            LIMIT $2
+        )",
+        EVENT_RETENTION_DAYS,
+        EVENT_REMOVAL_BATCH_SIZE
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected() as usize)
+}
+
+struct Delivery {
+    id: i64,
+    attempts: i32,
+    webhook_id: i32,
+    url: String,
+    secret: String,
+    event_id: i64,
+    event: String,
+    workspace_id: i32,
+    task_id: i32,
+    payload: serde_json::Value,
+    created_at: DateTime<Utc>,
+}
+
+/// Signs `{timestamp}.{body}` with HMAC-SHA256, so receivers can reject replayed deliveries.
+fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Sends one delivery, returning the response status, if any, and an error if it failed.
+async fn send(
+    client: &reqwest::Client,
+    allow_private_targets: bool,
+    delivery: &Delivery,
+) -> (Option<i32>, Option<String>) {
+    if !allow_private_targets {
+        if let Err(e) = check_target(&delivery.url) {
+            return (None, Some(e));
+        }
+    }
+    let body = json!({
+        "id": delivery.event_id,
+        "event": format!("task.{}", delivery.event),
+        "workspace_id": delivery.workspace_id,
+        "task_id": delivery.task_id,
+        "created_at": delivery.created_at,
+        "data": delivery.payload,
+    })
+    .to_string();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let response = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", delivery.webhook_id.to_string())
+        .header("X-Webhook-Delivery", delivery.id.to_string())
+        .header("X-Webhook-Event", format!("task.{}", delivery.event))
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            format!("sha256={}", sign(&delivery.secret, timestamp, &body)),
+        )
+        .body(body)
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => {
+            (Some(response.status().as_u16().into()), None)
+        }
+        Ok(response) => (
+            Some(response.status().as_u16().into()),
+            Some(format!("unexpected response status {}", response.status())),
+        ),
+        Err(e) => {
+            // Keeps the cause, such as a host without a public address, in the delivery log.
+            let mut error = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(cause) = source {
+                error.push_str(&format!(": {cause}"));
+                source = cause.source();
+            }
+            (None, Some(error))
+        }
+    }
+}
+
+// Claims are committed before anything is sent, so no transaction stays open while receivers
+// respond. A server that stops in between leaves its deliveries to be retried once the claim
+// runs out.
+async fn deliver_webhooks_batch(
+    db_pool: &PgPool,
+    client: &reqwest::Client,
+    allow_private_targets: bool,
+) -> Result<usize, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    // The worker delivers events of every workspace, so it reads them past row-level security.
+    sqlx::query_scalar!("SELECT set_config('app.bypass_rls', 'on', TRUE)")
+        .fetch_one(&mut *tx)
+        .await?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        "SELECT webhook_deliveries.id, webhook_deliveries.attempts, webhooks.id AS webhook_id,
+            webhooks.url, webhooks.secret, task_events.id AS event_id, task_events.event,
+            task_events.workspace_id, task_events.task_id, task_events.payload,
+            task_events.created_at
+        FROM webhook_deliveries
+        JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+        JOIN task_events ON task_events.id = webhook_deliveries.event_id
+        WHERE webhook_deliveries.status = 'pending' AND webhook_deliveries.next_attempt_at <= now()
+        ORDER BY webhook_deliveries.next_attempt_at
+        LIMIT $1
+        FOR UPDATE OF webhook_deliveries SKIP LOCKED",
+        DELIVERY_BATCH_SIZE
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let ids: Vec<i64> = deliveries.iter().map(|delivery| delivery.id).collect();
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET
+            attempts = attempts + 1,
+            next_attempt_at = now() + make_interval(secs => $2)
+        WHERE id = ANY($1)",
+        &ids,
+        CLAIM_SECONDS
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let outcomes = join_all(
+        deliveries
+            .iter()
+            .map(|delivery| send(client, allow_private_targets, delivery)),
+    )
+    .await;
+    let mut tx = db_pool.begin().await?;
+    sqlx::query_scalar!("SELECT set_config('app.bypass_rls', 'on', TRUE)")
+        .fetch_one(&mut *tx)
+        .await?;
+    for (delivery, (response_status, error)) in deliveries.iter().zip(outcomes) {
+        let attempts = delivery.attempts + 1;
+        let status = match error {
+            None => "succeeded",
+            Some(_) if attempts >= MAX_DELIVERY_ATTEMPTS => "failed",
+            Some(_) => "pending",
+        };
+        let retry_delay =
+            (RETRY_BASE_SECONDS * 2f64.powi(delivery.attempts)).min(RETRY_MAX_SECONDS);
+        sqlx::query!(
+            "UPDATE webhook_deliveries SET
+                status = $2,
+                response_status = $3,
+                last_error = $4,
+                next_attempt_at = now() + make_interval(secs => $5),
+                delivered_at = CASE WHEN $2 = 'succeeded' THEN now() END
+            WHERE id = $1",
+            delivery.id,
+            status,
+            response_status,
+            error,
+            retry_delay
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(deliveries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::record_task_event;
+    use crate::test_util::{insert_user, personal_workspace};
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "2002:0101:0101::1",
+        ] {
+            assert!(is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "192.0.0.8",
+            "198.18.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:7f00:0001::1",
+            "2002:a9fe:a9fe::",
+            "2002:c0a8:0101:1::1",
+            "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+            "2001::ffff:ffff:80ff:fffe",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn check_target_rejects_private_ip_hosts() {
+        assert!(check_target("https://example.com/hook").is_ok());
+        assert!(check_target("https://1.1.1.1/hook").is_ok());
+        assert!(check_target("http://127.0.0.1:8080/hook").is_err());
+        assert!(check_target("http://[::1]/hook").is_err());
+        assert!(check_target("http://169.254.169.254/latest/meta-data").is_err());
+    }
+
+    #[tokio::test]
+    async fn resolver_leaves_out_private_addresses() {
+        let name: Name = "localhost".parse().unwrap();
+        let error = PublicResolver.resolve(name).await.err().unwrap();
+        assert_eq!(error.to_string(), "localhost has no public address");
+    }
+
+    #[sqlx::test]
+    async fn deliveries_are_sent_after_they_are_claimed(db_pool: PgPool) {
+        let user_id = insert_user(&db_pool, "a@example.com").await;
+        let workspace_id = personal_workspace(&db_pool, user_id).await;
+        // Answers with whether the delivery can be locked, which it can't while a transaction
+        // holds it.
+        let receiver = axum::Router::new()
+            .route(
+                "/hook",
+                axum::routing::post(|State(db_pool): State<PgPool>| async move {
+                    let locked =
+                        sqlx::query!("SELECT id FROM webhook_deliveries FOR UPDATE NOWAIT")
+                            .fetch_all(&db_pool)
+                            .await;
+                    match locked {
+                        Ok(_) => StatusCode::NO_CONTENT,
+                        Err(_) => StatusCode::CONFLICT,
+                    }
+                }),
+            )
+            .with_state(db_pool.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async { axum::serve(listener, receiver).await });
+
+        sqlx::query!(
+            "INSERT INTO webhooks (workspace_id, url, secret, events)
+            VALUES ($1, $2, 'secret', ARRAY['created'])",
+            workspace_id,
+            url
+        )
+        .execute(&db_pool)
+        .await
+        .unwrap();
+        let mut conn = db_pool.acquire().await.unwrap();
+        record_task_event(&mut conn, workspace_id, 1, TaskEvent::Created, json!({}))
+            .await
+            .unwrap();
+
+        let claimed = deliver_webhooks_batch(&db_pool, &reqwest::Client::new(), true)
+            .await
+            .unwrap();
+        assert_eq!(claimed, 1);
+        let delivery = sqlx::query!(
+            "SELECT status, attempts, response_status, last_error FROM webhook_deliveries"
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(delivery.last_error, None);
+        assert_eq!(delivery.status, "succeeded");
+        assert_eq!(delivery.response_status, Some(204));
+        assert_eq!(delivery.attempts, 1);
+    }
+}
@@ -0,0 +1,26 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+/// Runs `batch` every `interval`. A batch returns how many items it claimed, at most
+/// `batch_size`; a full batch means more may be waiting, so it runs again straight away until
+/// they're done. Failures are logged as failing to `what`, and retried on the next tick.
+pub async fn run_batches<F, Fut, E>(what: &str, interval: Duration, batch_size: i64, mut batch: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<usize, E>>,
+    E: Display,
+{
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        loop {
+            match batch().await {
+                Ok(claimed) if claimed as i64 >= batch_size => continue,
+                Ok(_) => break,
+                Err(e) => {
+                    eprintln!("Failed to {what}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
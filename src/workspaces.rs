@@ -0,0 +1,509 @@
+use std::fmt;
+
+use axum::{extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, FieldError, Json, Path};
+use crate::events::TaskEvent;
+use crate::tasks::{assign_task, record_events_for_tasks};
+use crate::validation::{Valid, Validate, ValidationRules, Validator};
+
+/// A member's role in a workspace. Each role can do everything the roles before it can:
+/// viewers read tasks, editors also change them, and owners also manage the workspace.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Self::Viewer),
+            "editor" => Some(Self::Editor),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The permission check shared by every workspace and task handler: `role` is the caller's
+/// role, or `None` if they aren't a member. Non-members get a 404 for `resource`, so that
+/// workspaces they don't belong to stay hidden; members without `required` get a 403.
+pub fn check_role(
+    role: Option<&str>,
+    required: Role,
+    resource: &'static str,
+) -> Result<Role, AppError> {
+    let role = role
+        .and_then(Role::parse)
+        .ok_or(AppError::NotFound(resource))?;
+    if role < required {
+        return Err(AppError::Forbidden(
+            "insufficient_role",
+            format!("this requires the {required} role or higher; your role is {role}"),
+        ));
+    }
+    Ok(role)
+}
+
+/// Checks that the user has at least `required` in the workspace, returning their role.
+pub async fn require_role(
+    conn: &mut PgConnection,
+    user_id: i32,
+    workspace_id: i32,
+    required: Role,
+) -> Result<Role, AppError> {
+    let role = sqlx::query_scalar!(
+        "SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+        workspace_id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    check_role(role.as_deref(), required, "workspace")
+}
+
+#[derive(Serialize)]
+pub struct WorkspaceRow {
+    pub id: i32,
+    pub name: String,
+    pub personal: bool,
+    /// The caller's role.
+    pub role: String,
+}
+
+pub async fn get_workspaces(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let rows = sqlx::query_as!(
+        WorkspaceRow,
+        r#"SELECT workspaces.id, workspaces.name, workspaces.personal_for IS NOT NULL AS "personal!",
+            workspace_members.role
+        FROM workspaces JOIN workspace_members ON workspace_members.workspace_id = workspaces.id
+        WHERE workspace_members.user_id = $1
+        ORDER BY workspaces.id"#,
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+async fn fetch_workspace(
+    conn: &mut PgConnection,
+    user_id: i32,
+    id: i32,
+) -> Result<WorkspaceRow, AppError> {
+    sqlx::query_as!(
+        WorkspaceRow,
+        r#"SELECT workspaces.id, workspaces.name, workspaces.personal_for IS NOT NULL AS "personal!",
+            workspace_members.role
+        FROM workspaces JOIN workspace_members ON workspace_members.workspace_id = workspaces.id
+        WHERE workspaces.id = $1 AND workspace_members.user_id = $2"#,
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("workspace"))
+}
+
+pub async fn get_workspace(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let row = fetch_workspace(&mut tx, user.id, id).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct CreateWorkspaceRequest {
+    name: String,
+}
+
+impl Validate for CreateWorkspaceRequest {
+    fn validate(&mut self, rules: &ValidationRules) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.text("name", &mut self.name, rules.name_max_length);
+        validator.finish()
+    }
+}
+
+/// Creates a workspace with the given user as its only member and owner, and an Inbox as its
+/// default list. `personal` marks it as the user's personal workspace.
+pub async fn insert_workspace(
+    conn: &mut PgConnection,
+    user_id: i32,
+    name: &str,
+    personal: bool,
+) -> Result<i32, sqlx::Error> {
+    let id = sqlx::query_scalar!(
+        "INSERT INTO workspaces (name, personal_for)
+        VALUES ($1, CASE WHEN $3 THEN $2::INTEGER END)
+        RETURNING id",
+        name,
+        user_id,
+        personal
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, $3)",
+        id,
+        user_id,
+        Role::Owner.as_str()
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO lists (name, is_default, workspace_id) VALUES ('Inbox', TRUE, $1)",
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(id)
+}
+
+/// The workspace things go to when the user doesn't pick one.
+pub async fn personal_workspace_id(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar!("SELECT id FROM workspaces WHERE personal_for = $1", user_id)
+        .fetch_one(conn)
+        .await
+}
+
+pub async fn create_workspace(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Valid(workspace): Valid<CreateWorkspaceRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let id = insert_workspace(&mut tx, user.id, &workspace.name, false).await?;
+    let row = fetch_workspace(&mut tx, user.id, id).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+pub async fn update_workspace(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Valid(workspace): Valid<CreateWorkspaceRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, id, Role::Owner).await?;
+    sqlx::query!(
+        "UPDATE workspaces SET name = $2 WHERE id = $1",
+        id,
+        workspace.name
+    )
+    .execute(&mut *tx)
+    .await?;
+    let row = fetch_workspace(&mut tx, user.id, id).await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Deleting a workspace deletes its tasks. Personal workspaces can't be deleted, since tasks
+// created without a workspace go there. No task events are recorded: the workspace's event log,
+// webhooks and members are deleted with it, so there would be no one left to tell.
+pub async fn delete_workspace(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, id, Role::Owner).await?;
+    let result = sqlx::query!(
+        "DELETE FROM workspaces WHERE id = $1 AND personal_for IS NULL",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::Conflict(
+            "personal_workspace",
+            "a personal workspace cannot be deleted".to_owned(),
+        ));
+    }
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[derive(Serialize)]
+pub struct MemberRow {
+    pub user_id: i32,
+    pub email: String,
+    pub role: String,
+}
+
+pub async fn get_members(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, id, Role::Viewer).await?;
+    let rows = sqlx::query_as!(
+        MemberRow,
+        "SELECT workspace_members.user_id, users.email, workspace_members.role
+        FROM workspace_members JOIN users ON users.id = workspace_members.user_id
+        WHERE workspace_members.workspace_id = $1
+        ORDER BY workspace_members.user_id",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": rows }).to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct AddMemberRequest {
+    email: String,
+    role: Role,
+}
+
+pub async fn add_member(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    Json(member): Json<AddMemberRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, id, Role::Owner).await?;
+    let row = sqlx::query_as!(
+        MemberRow,
+        "INSERT INTO workspace_members (workspace_id, user_id, role)
+        SELECT $1, id, $3 FROM users WHERE lower(email) = lower($2)
+        RETURNING user_id, (SELECT email FROM users WHERE id = user_id) AS \"email!\", role",
+        id,
+        member.email,
+        member.role.as_str()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| {
+        AppError::Validation(vec![FieldError {
+            field: "email",
+            code: "not_found",
+            message: "must belong to a registered user".to_owned(),
+        }])
+    })?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Every workspace keeps at least one owner, and users can't be removed from or demoted in their
+// own personal workspace.
+async fn check_member_change(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    user_id: i32,
+    new_role: Option<Role>,
+) -> Result<(), AppError> {
+    // Serializes membership changes, so two owners can't demote each other at once.
+    let personal_for = sqlx::query_scalar!(
+        "SELECT personal_for FROM workspaces WHERE id = $1 FOR UPDATE",
+        workspace_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if personal_for == Some(user_id) && new_role != Some(Role::Owner) {
+        return Err(AppError::Conflict(
+            "personal_workspace",
+            "the owner of a personal workspace cannot be removed or demoted".to_owned(),
+        ));
+    }
+
+    let role = sqlx::query_scalar!(
+        "SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+        workspace_id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("member"))?;
+    if role != Role::Owner.as_str() || new_role == Some(Role::Owner) {
+        return Ok(());
+    }
+    let other_owners = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM workspace_members
+        WHERE workspace_id = $1 AND user_id <> $2 AND role = $3"#,
+        workspace_id,
+        user_id,
+        Role::Owner.as_str()
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if other_owners == 0 {
+        return Err(AppError::Conflict(
+            "last_owner",
+            "a workspace must keep at least one owner".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemberRequest {
+    role: Role,
+}
+
+pub async fn update_member(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((id, member_id)): Path<(i32, i32)>,
+    Json(member): Json<UpdateMemberRequest>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    require_role(&mut tx, user.id, id, Role::Owner).await?;
+    check_member_change(&mut tx, id, member_id, Some(member.role)).await?;
+    let row = sqlx::query_as!(
+        MemberRow,
+        "UPDATE workspace_members SET role = $3
+        WHERE workspace_id = $1 AND user_id = $2
+        RETURNING user_id, (SELECT email FROM users WHERE id = user_id) AS \"email!\", role",
+        id,
+        member_id,
+        member.role.as_str()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("member"))?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        json!({ "success": true, "data": row }).to_string(),
+    ))
+}
+
+// Owners can remove anyone; other members can only remove themselves. The member's tasks in the
+// workspace become unassigned.
+pub async fn remove_member(
+    State(db_pool): State<PgPool>,
+    user: AuthUser,
+    Path((id, member_id)): Path<(i32, i32)>,
+) -> Result<(StatusCode, String), AppError> {
+    let mut tx = user.begin(&db_pool).await?;
+    let required = if member_id == user.id {
+        Role::Viewer
+    } else {
+        Role::Owner
+    };
+    require_role(&mut tx, user.id, id, required).await?;
+    check_member_change(&mut tx, id, member_id, None).await?;
+    let assigned_task_ids = sqlx::query_scalar!(
+        "SELECT id FROM tasks WHERE workspace_id = $1 AND assignee_id = $2",
+        id,
+        member_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for &task_id in &assigned_task_ids {
+        assign_task(&mut tx, user.id, task_id, None).await?;
+    }
+    record_events_for_tasks(&mut tx, TaskEvent::Updated, &assigned_task_ids).await?;
+    sqlx::query!(
+        "DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+        id,
+        member_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, json!({ "success": true }).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_role_compares_against_the_required_role() {
+        let roles = [Role::Viewer, Role::Editor, Role::Owner];
+        for (i, role) in roles.iter().enumerate() {
+            for (j, required) in roles.iter().enumerate() {
+                let result = check_role(Some(role.as_str()), *required, "task");
+                if i >= j {
+                    assert!(matches!(result, Ok(granted) if granted == *role));
+                } else {
+                    assert!(matches!(
+                        result,
+                        Err(AppError::Forbidden("insufficient_role", _))
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn check_role_hides_resources_from_non_members() {
+        for required in [Role::Viewer, Role::Editor, Role::Owner] {
+            assert!(matches!(
+                check_role(None, required, "task"),
+                Err(AppError::NotFound("task"))
+            ));
+        }
+        // An unknown role in the database grants nothing.
+        assert!(matches!(
+            check_role(Some("admin"), Role::Viewer, "workspace"),
+            Err(AppError::NotFound("workspace"))
+        ));
+    }
+}